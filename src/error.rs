@@ -0,0 +1,145 @@
+use crate::tree_search::Key;
+use core::fmt;
+use nix::errno::Errno;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Ioctl {
+    TreeSearch,
+    TreeSearchV1,
+    LogicalIno,
+    LogicalInoV2,
+    GetSubvolInfo,
+    FsInfo,
+    SubvolCreate,
+    SnapCreate,
+    SnapDestroy,
+    SnapDestroyV2,
+    SubvolGetflags,
+    SubvolSetflags,
+    SetReceivedSubvol,
+    DefaultSubvol,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    Ioctl {
+        ioctl: Ioctl,
+        errno: Errno,
+    },
+    Sys(Errno),
+    NotBtrfs,
+    Truncated {
+        key: Option<Key>,
+        needed: usize,
+        available: usize,
+    },
+    UnknownKeyType(u32),
+    BufferTooSmall {
+        // the item that didn't fit, if the ioctl says which one
+        key: Option<Key>,
+        size: usize,
+    },
+    Decode {
+        key: Option<Key>,
+        what: &'static str,
+    },
+    ReceivedSubvol,
+}
+
+impl Error {
+    pub(crate) fn ioctl(ioctl: Ioctl) -> impl FnOnce(Errno) -> Self {
+        move |errno| Self::Ioctl { ioctl, errno }
+    }
+
+    pub fn errno(&self) -> Option<Errno> {
+        match self {
+            Self::Ioctl { errno, .. } | Self::Sys(errno) => Some(*errno),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Ioctl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::TreeSearch => "BTRFS_IOC_TREE_SEARCH_V2",
+            Self::TreeSearchV1 => "BTRFS_IOC_TREE_SEARCH",
+            Self::LogicalIno => "BTRFS_IOC_LOGICAL_INO",
+            Self::LogicalInoV2 => "BTRFS_IOC_LOGICAL_INO_V2",
+            Self::GetSubvolInfo => "BTRFS_IOC_GET_SUBVOL_INFO",
+            Self::FsInfo => "BTRFS_IOC_FS_INFO",
+            Self::SubvolCreate => "BTRFS_IOC_SUBVOL_CREATE_V2",
+            Self::SnapCreate => "BTRFS_IOC_SNAP_CREATE_V2",
+            Self::SnapDestroy => "BTRFS_IOC_SNAP_DESTROY",
+            Self::SnapDestroyV2 => "BTRFS_IOC_SNAP_DESTROY_V2",
+            Self::SubvolGetflags => "BTRFS_IOC_SUBVOL_GETFLAGS",
+            Self::SubvolSetflags => "BTRFS_IOC_SUBVOL_SETFLAGS",
+            Self::SetReceivedSubvol => "BTRFS_IOC_SET_RECEIVED_SUBVOL",
+            Self::DefaultSubvol => "BTRFS_IOC_DEFAULT_SUBVOL",
+        })
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ioctl { ioctl, errno } => write!(f, "{ioctl} failed: {errno}"),
+            Self::Sys(errno) => write!(f, "{errno}"),
+            Self::NotBtrfs => write!(f, "not a btrfs filesystem"),
+            Self::Truncated {
+                key: Some(key),
+                needed,
+                available,
+            } => write!(
+                f,
+                "item ({} {:?} {}) truncated: needed {needed} bytes but only {available} are available",
+                key.objectid, key.r#type, key.offset
+            ),
+            Self::Truncated {
+                key: None,
+                needed,
+                available,
+            } => write!(
+                f,
+                "truncated: needed {needed} bytes but only {available} are available"
+            ),
+            Self::UnknownKeyType(r#type) => write!(f, "unknown key type {type}"),
+            Self::BufferTooSmall {
+                key: Some(key),
+                size,
+            } => write!(
+                f,
+                "item ({} {:?} {}) doesn't fit into the ioctl buffer of {size} bytes",
+                key.objectid, key.r#type, key.offset
+            ),
+            Self::BufferTooSmall { key: None, size } => {
+                write!(f, "ioctl buffer of {size} bytes is too small")
+            }
+            Self::Decode { key: Some(key), what } => write!(
+                f,
+                "failed to decode {what} in item ({} {:?} {})",
+                key.objectid, key.r#type, key.offset
+            ),
+            Self::Decode { key: None, what } => write!(f, "failed to decode {what}"),
+            Self::ReceivedSubvol => write!(
+                f,
+                "subvolume was received, making it writable would break incremental send"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Ioctl { errno, .. } | Self::Sys(errno) => Some(errno),
+            _ => None,
+        }
+    }
+}
+
+impl From<nix::Error> for Error {
+    fn from(errno: nix::Error) -> Self {
+        Self::Sys(errno)
+    }
+}
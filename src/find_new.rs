@@ -0,0 +1,96 @@
+use crate::{
+    inode_links::inode_path,
+    item::{FileExtentInline, FileExtentReg},
+    tree_search::{Item, KeyRange, Tree},
+    Error, Subvolume, TreeSearch,
+};
+use std::{collections::HashMap, path::PathBuf};
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FileExtent {
+    Reg(FileExtentReg),
+    Inline(FileExtentInline),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ChangedInode {
+    pub inode: u64,
+    pub transid: u64,
+    // None for inodes that have been unlinked but not yet removed
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ChangedExtent {
+    pub inode: u64,
+    pub offset: u64,
+    pub generation: u64,
+    pub path: Option<PathBuf>,
+    pub extent: FileExtent,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Changes {
+    // pass this to the next changed_since to pick up where this one left off
+    pub generation: u64,
+    pub inodes: Vec<ChangedInode>,
+    pub extents: Vec<ChangedExtent>,
+}
+
+// only changes made after the given generation are included. a transaction
+// that is still running writes items with a transid above the generation read
+// here, so they are picked up by the next call
+pub(crate) fn changed_since(subvol: &Subvolume, generation: u64) -> Result<Changes, Error> {
+    let file = subvol.as_file();
+
+    // read before the search so anything written during it shows up next time
+    let current = subvol.info()?.generation;
+
+    let mut paths = HashMap::new();
+    let mut inodes = Vec::new();
+    let mut extents = Vec::new();
+
+    // the transid bounds only skip tree blocks that haven't changed, items in
+    // newer blocks can still be older than the generation we're looking for
+    let transids = generation.saturating_add(1)..=u64::MAX;
+
+    for result in TreeSearch::new(file, Tree::Auto, KeyRange::all(), transids) {
+        let (key, item) = result?;
+
+        let (transid, extent) = match item {
+            Item::Inode(inode) => (inode.transid.get(), None),
+            Item::FileExtentReg(extent) => (extent.generation.get(), Some(FileExtent::Reg(extent))),
+            Item::FileExtentInline(extent) => {
+                (extent.generation.get(), Some(FileExtent::Inline(extent)))
+            }
+            _ => continue,
+        };
+
+        if transid <= generation {
+            continue;
+        }
+
+        let path = inode_path(file, Tree::Auto, key.objectid, &mut paths)?;
+
+        match extent {
+            Some(extent) => extents.push(ChangedExtent {
+                inode: key.objectid,
+                offset: key.offset,
+                generation: transid,
+                path,
+                extent,
+            }),
+            None => inodes.push(ChangedInode {
+                inode: key.objectid,
+                transid,
+                path,
+            }),
+        }
+    }
+
+    Ok(Changes {
+        generation: current,
+        inodes,
+        extents,
+    })
+}
@@ -0,0 +1,125 @@
+use crate::{Error, Ioctl, Uuid};
+use btrfs_sys::{
+    btrfs_csum_type_BTRFS_CSUM_TYPE_BLAKE2, btrfs_csum_type_BTRFS_CSUM_TYPE_CRC32,
+    btrfs_csum_type_BTRFS_CSUM_TYPE_SHA256, btrfs_csum_type_BTRFS_CSUM_TYPE_XXHASH,
+    btrfs_ioctl_fs_info_args, BTRFS_FSID_SIZE, BTRFS_IOCTL_MAGIC,
+};
+use core::{convert::TryFrom, mem};
+use std::{fs::File, os::fd::AsRawFd};
+
+const BTRFS_FS_INFO_FLAG_CSUM_INFO: u64 = 1 << 0;
+const BTRFS_FS_INFO_FLAG_GENERATION: u64 = 1 << 1;
+const BTRFS_FS_INFO_FLAG_METADATA_UUID: u64 = 1 << 2;
+
+nix::ioctl_read!(
+    btrfs_fs_info,
+    BTRFS_IOCTL_MAGIC,
+    31,
+    btrfs_ioctl_fs_info_args
+);
+
+// btrfs_ioctl_fs_info_args from btrfs-sys predates the csum, generation and
+// metadata uuid fields, so we lay out the newer version of the struct ourselves
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct FsInfoArgs {
+    max_id: u64,
+    num_devices: u64,
+    fsid: [u8; BTRFS_FSID_SIZE as usize],
+    nodesize: u32,
+    sectorsize: u32,
+    clone_alignment: u32,
+    csum_type: u16,
+    csum_size: u16,
+    flags: u64,
+    generation: u64,
+    metadata_uuid: [u8; BTRFS_FSID_SIZE as usize],
+    reserved: [u8; 944],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CsumType {
+    Crc32,
+    Xxhash,
+    Sha256,
+    Blake2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FsInfo {
+    pub max_id: u64,
+    pub num_devices: u64,
+    pub fsid: Uuid,
+    pub nodesize: u32,
+    pub sectorsize: u32,
+    pub clone_alignment: u32,
+    pub csum_type: CsumType,
+    pub csum_size: usize,
+    pub generation: Option<u64>,
+    pub metadata_uuid: Option<Uuid>,
+}
+
+impl CsumType {
+    pub fn size(self) -> usize {
+        match self {
+            Self::Crc32 => 4,
+            Self::Xxhash => 8,
+            Self::Sha256 | Self::Blake2 => 32,
+        }
+    }
+}
+
+impl TryFrom<u16> for CsumType {
+    type Error = ();
+
+    #[allow(non_upper_case_globals)]
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        Ok(match value as u32 {
+            btrfs_csum_type_BTRFS_CSUM_TYPE_CRC32 => Self::Crc32,
+            btrfs_csum_type_BTRFS_CSUM_TYPE_XXHASH => Self::Xxhash,
+            btrfs_csum_type_BTRFS_CSUM_TYPE_SHA256 => Self::Sha256,
+            btrfs_csum_type_BTRFS_CSUM_TYPE_BLAKE2 => Self::Blake2,
+            _ => return Err(()),
+        })
+    }
+}
+
+pub fn fs_info(file: &File) -> Result<FsInfo, Error> {
+    let mut args: FsInfoArgs = unsafe { mem::zeroed() };
+
+    args.flags = BTRFS_FS_INFO_FLAG_CSUM_INFO
+        | BTRFS_FS_INFO_FLAG_GENERATION
+        | BTRFS_FS_INFO_FLAG_METADATA_UUID;
+
+    unsafe {
+        btrfs_fs_info(
+            file.as_raw_fd(),
+            (&mut args as *mut FsInfoArgs).cast::<btrfs_ioctl_fs_info_args>(),
+        )
+    }
+    .map_err(Error::ioctl(Ioctl::FsInfo))?;
+
+    // kernels older than 5.5 ignore the flags and only support crc32, in
+    // which case the csum fields are left zeroed
+    let csum_type = CsumType::try_from(args.csum_type).map_err(|()| Error::Decode {
+        key: None,
+        what: "csum type",
+    })?;
+
+    Ok(FsInfo {
+        max_id: args.max_id,
+        num_devices: args.num_devices,
+        fsid: Uuid(args.fsid),
+        nodesize: args.nodesize,
+        sectorsize: args.sectorsize,
+        clone_alignment: args.clone_alignment,
+        csum_type,
+        csum_size: match args.csum_size {
+            0 => csum_type.size(),
+            n => n as usize,
+        },
+        generation: (args.flags & BTRFS_FS_INFO_FLAG_GENERATION != 0).then_some(args.generation),
+        metadata_uuid: (args.flags & BTRFS_FS_INFO_FLAG_METADATA_UUID != 0)
+            .then_some(Uuid(args.metadata_uuid)),
+    })
+}
@@ -0,0 +1,76 @@
+use crate::{
+    tree_search::{Item, Key, KeyRange, KeyType, Tree},
+    Error, TreeSearch,
+};
+use btrfs_sys::BTRFS_FIRST_FREE_OBJECTID;
+use std::{collections::HashMap, fs::File, path::PathBuf};
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InodeLink {
+    pub parent: u64,
+    pub index: u64,
+    pub name: PathBuf,
+}
+
+// names come from both inode refs and the extended refs that are used once an
+// inode has too many links in one directory to fit in a single inode ref item
+pub fn inode_links(file: &File, tree: Tree, inode: u64) -> Result<Vec<InodeLink>, Error> {
+    let range = KeyRange::new(
+        Key::new(inode, KeyType::InodeRef, 0),
+        Key::new(inode, KeyType::InodeExtref, u64::MAX),
+    );
+    let search = TreeSearch::new(file, tree, range, 0..=u64::MAX);
+
+    let mut links = Vec::new();
+
+    for result in search {
+        match result? {
+            (key, Item::InodeRef(refs)) => {
+                links.extend(refs.into_iter().map(|inode_ref| InodeLink {
+                    parent: key.offset,
+                    index: inode_ref.index.get(),
+                    name: inode_ref.name,
+                }))
+            }
+            (_, Item::InodeExtref(extrefs)) => {
+                links.extend(extrefs.into_iter().map(|extref| InodeLink {
+                    parent: extref.parent.get(),
+                    index: extref.index.get(),
+                    name: extref.name,
+                }))
+            }
+            _ => continue,
+        }
+    }
+
+    Ok(links)
+}
+
+// the path of an inode relative to the root directory of its subvolume,
+// following the first link of every inode on the way up. paths caches the
+// directories already resolved in the same tree
+pub(crate) fn inode_path(
+    file: &File,
+    tree: Tree,
+    inode: u64,
+    paths: &mut HashMap<u64, Option<PathBuf>>,
+) -> Result<Option<PathBuf>, Error> {
+    if inode == BTRFS_FIRST_FREE_OBJECTID as u64 {
+        return Ok(Some(PathBuf::new()));
+    }
+
+    if let Some(path) = paths.get(&inode) {
+        return Ok(path.clone());
+    }
+
+    let path = match inode_links(file, tree, inode)?.into_iter().next() {
+        Some(link) if link.parent != inode => {
+            inode_path(file, tree, link.parent, paths)?.map(|parent| parent.join(link.name))
+        }
+        _ => None,
+    };
+
+    paths.insert(inode, path.clone());
+
+    Ok(path)
+}
@@ -1,18 +1,35 @@
-use crate::Uuid;
-use core::convert::{From, TryFrom};
-use std::{ffi::OsStr, os::unix::ffi::OsStrExt, path::PathBuf, time};
+use crate::{QgroupId, Uuid};
+use core::{convert::From, mem};
+use std::{ops::Range, path::PathBuf, time};
 
-use bitflags::{bitflags, Flags};
+use bitflags::bitflags;
 use btrfs_sys::{
-    btrfs_block_group_item, btrfs_compression_type_BTRFS_COMPRESS_LZO,
+    btrfs_balance_item, btrfs_block_group_item, btrfs_compression_type_BTRFS_COMPRESS_LZO,
     btrfs_compression_type_BTRFS_COMPRESS_NONE, btrfs_compression_type_BTRFS_COMPRESS_ZLIB,
-    btrfs_compression_type_BTRFS_COMPRESS_ZSTD, btrfs_dir_item, btrfs_disk_key,
-    btrfs_file_extent_item, btrfs_free_space_header, btrfs_inode_item, btrfs_inode_ref,
-    btrfs_root_item, btrfs_root_ref, BTRFS_BLOCK_GROUP_DATA, BTRFS_BLOCK_GROUP_DUP,
-    BTRFS_BLOCK_GROUP_METADATA, BTRFS_BLOCK_GROUP_RAID0, BTRFS_BLOCK_GROUP_RAID1,
-    BTRFS_BLOCK_GROUP_RAID10, BTRFS_BLOCK_GROUP_RAID5, BTRFS_BLOCK_GROUP_RAID6,
-    BTRFS_BLOCK_GROUP_SYSTEM, BTRFS_FT_BLKDEV, BTRFS_FT_CHRDEV, BTRFS_FT_DIR, BTRFS_FT_FIFO,
-    BTRFS_FT_REG_FILE, BTRFS_FT_SYMLINK, BTRFS_FT_XATTR, BTRFS_ROOT_SUBVOL_RDONLY,
+    btrfs_compression_type_BTRFS_COMPRESS_ZSTD, btrfs_dev_extent, btrfs_dev_item,
+    btrfs_dev_stat_values_BTRFS_DEV_STAT_CORRUPTION_ERRS,
+    btrfs_dev_stat_values_BTRFS_DEV_STAT_FLUSH_ERRS,
+    btrfs_dev_stat_values_BTRFS_DEV_STAT_GENERATION_ERRS,
+    btrfs_dev_stat_values_BTRFS_DEV_STAT_READ_ERRS,
+    btrfs_dev_stat_values_BTRFS_DEV_STAT_WRITE_ERRS, btrfs_dev_stats_item, btrfs_dir_log_item,
+    btrfs_disk_balance_args, btrfs_disk_key, btrfs_extent_data_ref, btrfs_file_extent_item,
+    btrfs_free_space_header, btrfs_free_space_info, btrfs_inode_item, btrfs_qgroup_info_item,
+    btrfs_qgroup_limit_item, btrfs_qgroup_status_item, btrfs_root_item, btrfs_shared_data_ref,
+    btrfs_stripe, btrfs_tree_block_info, BTRFS_BLOCK_FLAG_FULL_BACKREF, BTRFS_BLOCK_GROUP_DATA,
+    BTRFS_BLOCK_GROUP_DUP, BTRFS_BLOCK_GROUP_METADATA, BTRFS_BLOCK_GROUP_RAID0,
+    BTRFS_BLOCK_GROUP_RAID1, BTRFS_BLOCK_GROUP_RAID10, BTRFS_BLOCK_GROUP_RAID1C3,
+    BTRFS_BLOCK_GROUP_RAID1C4, BTRFS_BLOCK_GROUP_RAID5, BTRFS_BLOCK_GROUP_RAID6,
+    BTRFS_BLOCK_GROUP_SYSTEM, BTRFS_EXTENT_DATA_REF_KEY, BTRFS_EXTENT_FLAG_DATA,
+    BTRFS_EXTENT_FLAG_TREE_BLOCK, BTRFS_FREE_SPACE_USING_BITMAPS, BTRFS_FT_BLKDEV, BTRFS_FT_CHRDEV,
+    BTRFS_FT_DIR, BTRFS_FT_FIFO, BTRFS_FT_REG_FILE, BTRFS_FT_SOCK, BTRFS_FT_SYMLINK,
+    BTRFS_IOCTL_DEV_REPLACE_STATE_CANCELED, BTRFS_IOCTL_DEV_REPLACE_STATE_FINISHED,
+    BTRFS_IOCTL_DEV_REPLACE_STATE_NEVER_STARTED, BTRFS_IOCTL_DEV_REPLACE_STATE_STARTED,
+    BTRFS_IOCTL_DEV_REPLACE_STATE_SUSPENDED, BTRFS_QGROUP_LIMIT_EXCL_CMPR,
+    BTRFS_QGROUP_LIMIT_MAX_EXCL, BTRFS_QGROUP_LIMIT_MAX_RFER, BTRFS_QGROUP_LIMIT_RFER_CMPR,
+    BTRFS_QGROUP_LIMIT_RSV_EXCL, BTRFS_QGROUP_LIMIT_RSV_RFER,
+    BTRFS_QGROUP_STATUS_FLAG_INCONSISTENT, BTRFS_QGROUP_STATUS_FLAG_ON,
+    BTRFS_QGROUP_STATUS_FLAG_RESCAN, BTRFS_ROOT_SUBVOL_RDONLY, BTRFS_SHARED_BLOCK_REF_KEY,
+    BTRFS_SHARED_DATA_REF_KEY, BTRFS_TREE_BLOCK_REF_KEY,
 };
 
 use crate::{le, Compression};
@@ -42,6 +59,13 @@ pub struct InodeRef {
     pub name: PathBuf,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InodeExtref {
+    pub parent: le::U64,
+    pub index: le::U64,
+    pub name: PathBuf,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct DiskKey {
     pub objectid: le::U64,
@@ -165,6 +189,8 @@ bitflags! {
         const RAID5 = BTRFS_BLOCK_GROUP_RAID5 as u64;
         const RAID6 = BTRFS_BLOCK_GROUP_RAID6 as u64;
         const RAID10 = BTRFS_BLOCK_GROUP_RAID10 as u64;
+        const RAID1C3 = BTRFS_BLOCK_GROUP_RAID1C3 as u64;
+        const RAID1C4 = BTRFS_BLOCK_GROUP_RAID1C4 as u64;
     }
 
 }
@@ -176,6 +202,366 @@ pub struct BlockGroup {
     pub flags: BlockGroupFlag,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Stripe {
+    pub devid: le::U64,
+    pub offset: le::U64,
+    pub dev_uuid: Uuid,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Chunk {
+    pub length: le::U64,
+    pub owner: le::U64,
+    pub stripe_len: le::U64,
+    pub r#type: BlockGroupFlag,
+    pub io_align: le::U32,
+    pub io_width: le::U32,
+    pub sector_size: le::U32,
+    pub num_stripes: le::U16,
+    pub sub_stripes: le::U16,
+    pub stripes: Vec<Stripe>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DevItem {
+    pub devid: le::U64,
+    pub total_bytes: le::U64,
+    pub bytes_used: le::U64,
+    pub io_align: le::U32,
+    pub io_width: le::U32,
+    pub sector_size: le::U32,
+    pub r#type: le::U64,
+    pub generation: le::U64,
+    pub start_offset: le::U64,
+    pub dev_group: le::U32,
+    pub seek_speed: u8,
+    pub bandwidth: u8,
+    pub uuid: Uuid,
+    pub fsid: Uuid,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DevExtent {
+    pub chunk_tree: le::U64,
+    pub chunk_objectid: le::U64,
+    pub chunk_offset: le::U64,
+    pub length: le::U64,
+    pub chunk_tree_uuid: Uuid,
+}
+
+bitflags! {
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct ExtentFlag: u64 {
+        const DATA = BTRFS_EXTENT_FLAG_DATA as u64;
+        const TREE_BLOCK = BTRFS_EXTENT_FLAG_TREE_BLOCK as u64;
+        const FULL_BACKREF = BTRFS_BLOCK_FLAG_FULL_BACKREF as u64;
+    }
+
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TreeBlockInfo {
+    pub key: DiskKey,
+    pub level: u8,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ExtentInlineRef {
+    TreeBlock {
+        root: le::U64,
+    },
+    SharedBlock {
+        parent: le::U64,
+    },
+    ExtentData {
+        root: le::U64,
+        objectid: le::U64,
+        offset: le::U64,
+        count: le::U32,
+    },
+    SharedData {
+        parent: le::U64,
+        count: le::U32,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Extent {
+    pub refs: le::U64,
+    pub generation: le::U64,
+    pub flags: ExtentFlag,
+    pub tree_block_info: Option<TreeBlockInfo>,
+    pub inline_refs: Vec<ExtentInlineRef>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TreeBlockRef {
+    pub root: le::U64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SharedBlockRef {
+    pub parent: le::U64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ExtentDataRef {
+    pub hash: le::U64,
+    pub root: le::U64,
+    pub objectid: le::U64,
+    pub offset: le::U64,
+    pub count: le::U32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SharedDataRef {
+    pub parent: le::U64,
+    pub count: le::U32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Csum {
+    pub start: le::U64,
+    pub sums: Vec<Vec<u8>>,
+}
+
+bitflags! {
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct FreeSpaceInfoFlag: u32 {
+        const USING_BITMAPS = BTRFS_FREE_SPACE_USING_BITMAPS;
+    }
+
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FreeSpaceInfo {
+    pub extent_count: le::U32,
+    pub flags: FreeSpaceInfoFlag,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FreeSpaceExtent {
+    pub start: u64,
+    pub length: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FreeSpaceBitmap {
+    pub start: u64,
+    pub length: u64,
+    pub sectorsize: u32,
+    pub bitmap: Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+pub struct FreeRanges<'a> {
+    start: u64,
+    sectorsize: u32,
+    sectors: u64,
+    bitmap: &'a [u8],
+    bit: u64,
+}
+
+bitflags! {
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct QgroupStatusFlag: u64 {
+        const ON = BTRFS_QGROUP_STATUS_FLAG_ON as u64;
+        const RESCAN = BTRFS_QGROUP_STATUS_FLAG_RESCAN as u64;
+        const INCONSISTENT = BTRFS_QGROUP_STATUS_FLAG_INCONSISTENT as u64;
+        // simple quotas, not yet in the headers btrfs-sys was generated from
+        const SIMPLE_MODE = 1 << 3;
+    }
+
+}
+
+bitflags! {
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct QgroupLimitFlag: u64 {
+        const MAX_RFER = BTRFS_QGROUP_LIMIT_MAX_RFER as u64;
+        const MAX_EXCL = BTRFS_QGROUP_LIMIT_MAX_EXCL as u64;
+        const RSV_RFER = BTRFS_QGROUP_LIMIT_RSV_RFER as u64;
+        const RSV_EXCL = BTRFS_QGROUP_LIMIT_RSV_EXCL as u64;
+        const RFER_CMPR = BTRFS_QGROUP_LIMIT_RFER_CMPR as u64;
+        const EXCL_CMPR = BTRFS_QGROUP_LIMIT_EXCL_CMPR as u64;
+    }
+
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct QgroupStatus {
+    pub version: le::U64,
+    pub generation: le::U64,
+    pub flags: QgroupStatusFlag,
+    pub rescan: le::U64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct QgroupInfo {
+    pub qgroupid: QgroupId,
+    pub generation: le::U64,
+    pub referenced: le::U64,
+    pub referenced_compressed: le::U64,
+    pub exclusive: le::U64,
+    pub exclusive_compressed: le::U64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct QgroupLimit {
+    pub qgroupid: QgroupId,
+    pub flags: QgroupLimitFlag,
+    pub max_referenced: le::U64,
+    pub max_exclusive: le::U64,
+    pub rsv_referenced: le::U64,
+    pub rsv_exclusive: le::U64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct QgroupRelation {
+    pub src: QgroupId,
+    pub dst: QgroupId,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UuidItem {
+    pub uuid: Uuid,
+    pub subvol_ids: Vec<le::U64>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Orphan {
+    pub objectid: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DirLog {
+    pub end: le::U64,
+}
+
+// these are macros in the kernel headers that bindgen doesn't pick up
+bitflags! {
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct BalanceFlag: u64 {
+        const DATA = 1 << 0;
+        const SYSTEM = 1 << 1;
+        const METADATA = 1 << 2;
+        const FORCE = 1 << 3;
+        const RESUME = 1 << 4;
+    }
+
+}
+
+bitflags! {
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct BalanceArgsFlag: u64 {
+        const PROFILES = 1 << 0;
+        const USAGE = 1 << 1;
+        const DEVID = 1 << 2;
+        const DRANGE = 1 << 3;
+        const VRANGE = 1 << 4;
+        const LIMIT = 1 << 5;
+        const LIMIT_RANGE = 1 << 6;
+        const STRIPES_RANGE = 1 << 7;
+        const CONVERT = 1 << 8;
+        const SOFT = 1 << 9;
+        const USAGE_RANGE = 1 << 10;
+    }
+
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BalanceArgs {
+    pub profiles: le::U64,
+    pub usage: le::U64,
+    pub devid: le::U64,
+    pub pstart: le::U64,
+    pub pend: le::U64,
+    pub vstart: le::U64,
+    pub vend: le::U64,
+    pub target: le::U64,
+    pub flags: BalanceArgsFlag,
+    pub limit: le::U64,
+    pub stripes_min: le::U32,
+    pub stripes_max: le::U32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Balance {
+    pub flags: BalanceFlag,
+    pub data: BalanceArgs,
+    pub meta: BalanceArgs,
+    pub sys: BalanceArgs,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DevStats {
+    pub devid: u64,
+    pub write_errs: le::U64,
+    pub read_errs: le::U64,
+    pub flush_errs: le::U64,
+    pub corruption_errs: le::U64,
+    pub generation_errs: le::U64,
+}
+
+// btrfs_dev_replace_item isn't part of the headers btrfs-sys was generated from
+#[repr(C, packed)]
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct DevReplaceItem {
+    src_devid: u64,
+    cursor_left: u64,
+    cursor_right: u64,
+    cont_reading_from_srcdev_mode: u64,
+    replace_state: u64,
+    time_started: u64,
+    time_stopped: u64,
+    num_write_errors: u64,
+    num_uncorrectable_read_errors: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DevReplaceState {
+    NeverStarted,
+    Started,
+    Finished,
+    Canceled,
+    Suspended,
+}
+
+// the replace target isn't recorded in the item, it always gets this reserved
+// devid while the replace is running
+pub const DEV_REPLACE_TGT_DEVID: u64 = 0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DevReplace {
+    pub src_devid: le::U64,
+    pub cursor_left: le::U64,
+    pub cursor_right: le::U64,
+    pub cont_reading_from_srcdev_mode: le::U64,
+    pub state: DevReplaceState,
+    pub time_started: time::Duration,
+    pub time_stopped: time::Duration,
+    pub num_write_errors: le::U64,
+    pub num_uncorrectable_read_errors: le::U64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum VerityDescItem {
+    Info { size: le::U64, encryption: u8 },
+    Data { offset: u64, data: Vec<u8> },
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VerityMerkle {
+    pub offset: u64,
+    pub data: Vec<u8>,
+}
+
 impl BlockGroup {
     pub(crate) fn from_c_struct(block_group: btrfs_block_group_item) -> Result<Self, ()> {
         Ok(Self {
@@ -186,6 +572,356 @@ impl BlockGroup {
     }
 }
 
+impl Stripe {
+    pub(crate) fn from_c_struct(stripe: btrfs_stripe) -> Self {
+        Self {
+            devid: le::U64::new(stripe.devid),
+            offset: le::U64::new(stripe.offset),
+            dev_uuid: Uuid(stripe.dev_uuid),
+        }
+    }
+}
+
+impl DevItem {
+    pub(crate) fn from_c_struct(dev_item: btrfs_dev_item) -> Self {
+        Self {
+            devid: le::U64::new(dev_item.devid),
+            total_bytes: le::U64::new(dev_item.total_bytes),
+            bytes_used: le::U64::new(dev_item.bytes_used),
+            io_align: le::U32::new(dev_item.io_align),
+            io_width: le::U32::new(dev_item.io_width),
+            sector_size: le::U32::new(dev_item.sector_size),
+            r#type: le::U64::new(dev_item.type_),
+            generation: le::U64::new(dev_item.generation),
+            start_offset: le::U64::new(dev_item.start_offset),
+            dev_group: le::U32::new(dev_item.dev_group),
+            seek_speed: dev_item.seek_speed,
+            bandwidth: dev_item.bandwidth,
+            uuid: Uuid(dev_item.uuid),
+            fsid: Uuid(dev_item.fsid),
+        }
+    }
+}
+
+impl DevExtent {
+    pub(crate) fn from_c_struct(dev_extent: btrfs_dev_extent) -> Self {
+        Self {
+            chunk_tree: le::U64::new(dev_extent.chunk_tree),
+            chunk_objectid: le::U64::new(dev_extent.chunk_objectid),
+            chunk_offset: le::U64::new(dev_extent.chunk_offset),
+            length: le::U64::new(dev_extent.length),
+            chunk_tree_uuid: Uuid(dev_extent.chunk_tree_uuid),
+        }
+    }
+}
+
+impl TreeBlockInfo {
+    pub(crate) fn from_c_struct(info: btrfs_tree_block_info) -> Self {
+        Self {
+            key: DiskKey::from_c_struct(info.key),
+            level: info.level,
+        }
+    }
+}
+
+impl ExtentInlineRef {
+    // returns the parsed ref and the number of bytes it occupied
+    pub(crate) fn from_bytes(data: &[u8]) -> Result<(Self, usize), ()> {
+        let r#type = *data.first().ok_or(())?;
+        let data = &data[1..];
+
+        let read_u64 = |offset: usize| -> Result<u64, ()> {
+            Ok(u64::from_le_bytes(
+                data.get(offset..offset + 8).ok_or(())?.try_into().unwrap(),
+            ))
+        };
+
+        let read_u32 = |offset: usize| -> Result<u32, ()> {
+            Ok(u32::from_le_bytes(
+                data.get(offset..offset + 4).ok_or(())?.try_into().unwrap(),
+            ))
+        };
+
+        Ok(match r#type as u32 {
+            BTRFS_TREE_BLOCK_REF_KEY => (
+                Self::TreeBlock {
+                    root: le::U64::new(read_u64(0)?),
+                },
+                1 + mem::size_of::<u64>(),
+            ),
+            BTRFS_SHARED_BLOCK_REF_KEY => (
+                Self::SharedBlock {
+                    parent: le::U64::new(read_u64(0)?),
+                },
+                1 + mem::size_of::<u64>(),
+            ),
+            // the extent data ref overlaps the offset field of the inline ref
+            BTRFS_EXTENT_DATA_REF_KEY => (
+                Self::ExtentData {
+                    root: le::U64::new(read_u64(mem::offset_of!(btrfs_extent_data_ref, root))?),
+                    objectid: le::U64::new(read_u64(mem::offset_of!(
+                        btrfs_extent_data_ref,
+                        objectid
+                    ))?),
+                    offset: le::U64::new(read_u64(mem::offset_of!(btrfs_extent_data_ref, offset))?),
+                    count: le::U32::new(read_u32(mem::offset_of!(btrfs_extent_data_ref, count))?),
+                },
+                1 + mem::size_of::<btrfs_extent_data_ref>(),
+            ),
+            BTRFS_SHARED_DATA_REF_KEY => (
+                Self::SharedData {
+                    parent: le::U64::new(read_u64(0)?),
+                    count: le::U32::new(read_u32(mem::size_of::<u64>())?),
+                },
+                1 + mem::size_of::<u64>() + mem::size_of::<btrfs_shared_data_ref>(),
+            ),
+            _ => return Err(()),
+        })
+    }
+}
+
+impl TreeBlockRef {
+    pub(crate) fn from_key_offset(offset: u64) -> Self {
+        Self {
+            root: le::U64::new(offset),
+        }
+    }
+}
+
+impl SharedBlockRef {
+    pub(crate) fn from_key_offset(offset: u64) -> Self {
+        Self {
+            parent: le::U64::new(offset),
+        }
+    }
+}
+
+impl ExtentDataRef {
+    pub(crate) fn from_c_struct(data_ref: btrfs_extent_data_ref, offset: u64) -> Self {
+        Self {
+            hash: le::U64::new(offset),
+            root: le::U64::new(data_ref.root),
+            objectid: le::U64::new(data_ref.objectid),
+            offset: le::U64::new(data_ref.offset),
+            count: le::U32::new(data_ref.count),
+        }
+    }
+}
+
+impl SharedDataRef {
+    pub(crate) fn from_c_struct(data_ref: btrfs_shared_data_ref, offset: u64) -> Self {
+        Self {
+            parent: le::U64::new(offset),
+            count: le::U32::new(data_ref.count),
+        }
+    }
+}
+
+impl FreeSpaceInfo {
+    pub(crate) fn from_c_struct(info: btrfs_free_space_info) -> Result<Self, ()> {
+        Ok(Self {
+            extent_count: le::U32::new(info.extent_count),
+            flags: FreeSpaceInfoFlag::from_bits(info.flags).ok_or(())?,
+        })
+    }
+}
+
+impl FreeSpaceExtent {
+    pub fn range(&self) -> Range<u64> {
+        self.start..self.start + self.length
+    }
+}
+
+impl FreeSpaceBitmap {
+    pub fn range(&self) -> Range<u64> {
+        self.start..self.start + self.length
+    }
+
+    pub fn is_free(&self, sector: u64) -> bool {
+        sector_is_free(&self.bitmap, sector)
+    }
+
+    pub fn free_ranges(&self) -> FreeRanges<'_> {
+        FreeRanges::new(self.start, self.length, self.sectorsize, &self.bitmap)
+    }
+}
+
+// bit n of the bitmap is set if sector n is free, bits are numbered from the
+// least significant bit of each byte
+pub(crate) fn sector_is_free(bitmap: &[u8], sector: u64) -> bool {
+    bitmap
+        .get((sector / 8) as usize)
+        .is_some_and(|byte| byte & (1 << (sector % 8)) != 0)
+}
+
+impl<'a> FreeRanges<'a> {
+    pub(crate) fn new(start: u64, length: u64, sectorsize: u32, bitmap: &'a [u8]) -> Self {
+        Self {
+            start,
+            sectorsize,
+            sectors: length / u64::from(sectorsize),
+            bitmap,
+            bit: 0,
+        }
+    }
+}
+
+impl Iterator for FreeRanges<'_> {
+    type Item = Range<u64>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.bit < self.sectors && !sector_is_free(self.bitmap, self.bit) {
+            self.bit += 1;
+        }
+
+        if self.bit >= self.sectors {
+            return None;
+        }
+
+        let first = self.bit;
+
+        while self.bit < self.sectors && sector_is_free(self.bitmap, self.bit) {
+            self.bit += 1;
+        }
+
+        let sectorsize = u64::from(self.sectorsize);
+
+        Some(self.start + first * sectorsize..self.start + self.bit * sectorsize)
+    }
+}
+
+impl QgroupStatus {
+    pub(crate) fn from_c_struct(status: btrfs_qgroup_status_item) -> Result<Self, ()> {
+        Ok(Self {
+            version: le::U64::new(status.version),
+            generation: le::U64::new(status.generation),
+            flags: QgroupStatusFlag::from_bits(status.flags).ok_or(())?,
+            rescan: le::U64::new(status.rescan),
+        })
+    }
+}
+
+impl QgroupInfo {
+    pub(crate) fn from_c_struct(info: btrfs_qgroup_info_item, qgroupid: u64) -> Self {
+        Self {
+            qgroupid: QgroupId(qgroupid),
+            generation: le::U64::new(info.generation),
+            referenced: le::U64::new(info.referenced),
+            referenced_compressed: le::U64::new(info.referenced_compressed),
+            exclusive: le::U64::new(info.exclusive),
+            exclusive_compressed: le::U64::new(info.exclusive_compressed),
+        }
+    }
+}
+
+impl QgroupLimit {
+    pub(crate) fn from_c_struct(limit: btrfs_qgroup_limit_item, qgroupid: u64) -> Result<Self, ()> {
+        Ok(Self {
+            qgroupid: QgroupId(qgroupid),
+            flags: QgroupLimitFlag::from_bits(limit.flags).ok_or(())?,
+            max_referenced: le::U64::new(limit.max_referenced),
+            max_exclusive: le::U64::new(limit.max_exclusive),
+            rsv_referenced: le::U64::new(limit.rsv_referenced),
+            rsv_exclusive: le::U64::new(limit.rsv_exclusive),
+        })
+    }
+}
+
+impl DirLog {
+    pub(crate) fn from_c_struct(dir_log: btrfs_dir_log_item) -> Self {
+        Self {
+            end: le::U64::new(dir_log.end),
+        }
+    }
+}
+
+impl BalanceArgs {
+    pub(crate) fn from_c_struct(args: btrfs_disk_balance_args) -> Result<Self, ()> {
+        Ok(Self {
+            profiles: le::U64::new(args.profiles),
+            usage: le::U64::new(unsafe { args.__bindgen_anon_1.usage }),
+            devid: le::U64::new(args.devid),
+            pstart: le::U64::new(args.pstart),
+            pend: le::U64::new(args.pend),
+            vstart: le::U64::new(args.vstart),
+            vend: le::U64::new(args.vend),
+            target: le::U64::new(args.target),
+            flags: BalanceArgsFlag::from_bits(args.flags).ok_or(())?,
+            limit: le::U64::new(unsafe { args.__bindgen_anon_2.limit }),
+            stripes_min: le::U32::new(args.stripes_min),
+            stripes_max: le::U32::new(args.stripes_max),
+        })
+    }
+
+    // usage and limit are unions with a min/max pair when the *_RANGE flags are set
+    pub fn usage_range(&self) -> (u32, u32) {
+        (self.usage.get() as u32, (self.usage.get() >> 32) as u32)
+    }
+
+    pub fn limit_range(&self) -> (u32, u32) {
+        (self.limit.get() as u32, (self.limit.get() >> 32) as u32)
+    }
+}
+
+impl Balance {
+    pub(crate) fn from_c_struct(balance: btrfs_balance_item) -> Result<Self, ()> {
+        Ok(Self {
+            flags: BalanceFlag::from_bits(balance.flags).ok_or(())?,
+            data: BalanceArgs::from_c_struct(balance.data)?,
+            meta: BalanceArgs::from_c_struct(balance.meta)?,
+            sys: BalanceArgs::from_c_struct(balance.sys)?,
+        })
+    }
+}
+
+impl DevStats {
+    pub(crate) fn from_c_struct(stats: btrfs_dev_stats_item, devid: u64) -> Self {
+        let values = stats.values;
+
+        Self {
+            devid,
+            write_errs: le::U64::new(
+                values[btrfs_dev_stat_values_BTRFS_DEV_STAT_WRITE_ERRS as usize],
+            ),
+            read_errs: le::U64::new(
+                values[btrfs_dev_stat_values_BTRFS_DEV_STAT_READ_ERRS as usize],
+            ),
+            flush_errs: le::U64::new(
+                values[btrfs_dev_stat_values_BTRFS_DEV_STAT_FLUSH_ERRS as usize],
+            ),
+            corruption_errs: le::U64::new(
+                values[btrfs_dev_stat_values_BTRFS_DEV_STAT_CORRUPTION_ERRS as usize],
+            ),
+            generation_errs: le::U64::new(
+                values[btrfs_dev_stat_values_BTRFS_DEV_STAT_GENERATION_ERRS as usize],
+            ),
+        }
+    }
+}
+
+impl DevReplace {
+    pub(crate) fn from_c_struct(dev_replace: DevReplaceItem) -> Result<Self, ()> {
+        Ok(Self {
+            src_devid: le::U64::new(dev_replace.src_devid),
+            cursor_left: le::U64::new(dev_replace.cursor_left),
+            cursor_right: le::U64::new(dev_replace.cursor_right),
+            cont_reading_from_srcdev_mode: le::U64::new(dev_replace.cont_reading_from_srcdev_mode),
+            state: match dev_replace.replace_state as u32 {
+                BTRFS_IOCTL_DEV_REPLACE_STATE_NEVER_STARTED => DevReplaceState::NeverStarted,
+                BTRFS_IOCTL_DEV_REPLACE_STATE_STARTED => DevReplaceState::Started,
+                BTRFS_IOCTL_DEV_REPLACE_STATE_FINISHED => DevReplaceState::Finished,
+                BTRFS_IOCTL_DEV_REPLACE_STATE_CANCELED => DevReplaceState::Canceled,
+                BTRFS_IOCTL_DEV_REPLACE_STATE_SUSPENDED => DevReplaceState::Suspended,
+                _ => return Err(()),
+            },
+            time_started: time::Duration::from_secs(dev_replace.time_started),
+            time_stopped: time::Duration::from_secs(dev_replace.time_stopped),
+            num_write_errors: le::U64::new(dev_replace.num_write_errors),
+            num_uncorrectable_read_errors: le::U64::new(dev_replace.num_uncorrectable_read_errors),
+        })
+    }
+}
+
 impl Inode {
     pub(crate) fn from_c_struct(inode: btrfs_inode_item) -> Self {
         Self {
@@ -212,15 +948,6 @@ impl Inode {
     }
 }
 
-impl InodeRef {
-    pub(crate) fn from_c_struct(inode_ref: btrfs_inode_ref, data: &[u8]) -> Self {
-        Self {
-            index: le::U64::new(inode_ref.index),
-            name: PathBuf::from(<OsStr as OsStrExt>::from_bytes(data)),
-        }
-    }
-}
-
 impl DiskKey {
     pub(crate) fn from_c_struct(key: btrfs_disk_key) -> Self {
         Self {
@@ -241,17 +968,13 @@ impl Root {
             byte_limit: le::U64::new(root.byte_limit),
             bytes_used: le::U64::new(root.bytes_used),
             last_snapshot: le::U64::new(root.last_snapshot),
-            read_only: matches!(root.flags as u32, BTRFS_ROOT_SUBVOL_RDONLY),
-            refs: match root.refs {
-                0 => false,
-                1 => true,
-                _ => unreachable!(),
-            },
+            read_only: root.flags & BTRFS_ROOT_SUBVOL_RDONLY as u64 != 0,
+            refs: root.refs != 0,
             btrfs_disk_key: DiskKey::from_c_struct(root.drop_progress),
             level: root.level,
             generation_v2: le::U64::new(root.generation_v2),
             uuid: Uuid(root.uuid),
-            parent_uuid: Uuid(root.uuid),
+            parent_uuid: Uuid(root.parent_uuid),
             received_uuid: Uuid(root.received_uuid),
             ctransid: le::U64::new(root.ctransid),
             rtransid: le::U64::new(root.rtransid),
@@ -268,107 +991,44 @@ impl Root {
     }
 }
 
-impl RootRef {
-    pub(crate) fn from_c_struct(root_ref: btrfs_root_ref, data: &[u8]) -> Self {
-        Self {
-            dirid: le::U64::new(root_ref.dirid),
-            sequence: le::U64::new(root_ref.sequence),
-            name: PathBuf::from(<OsStr as OsStrExt>::from_bytes(data)),
-        }
-    }
-}
-
-impl DirItem {
-    pub(crate) fn from_c_struct(dir: btrfs_dir_item, data: &[u8]) -> Self {
-        match dir.type_ as u32 {
-            BTRFS_FT_XATTR => Self::Xattr {
-                location: DiskKey::from_c_struct(dir.location),
-                transid: le::U64::new(dir.transid),
-                name: data[..dir.name_len as usize].to_vec(),
-                value: data[dir.data_len as usize..].to_vec(),
-            },
-            _ => Self::File {
-                location: DiskKey::from_c_struct(dir.location),
-                transid: le::U64::new(dir.transid),
-                r#type: match dir.type_ as u32 {
-                    BTRFS_FT_REG_FILE => FileType::Reg,
-                    BTRFS_FT_DIR => FileType::Dir,
-                    BTRFS_FT_CHRDEV => FileType::ChrDev,
-                    BTRFS_FT_BLKDEV => FileType::BlkDev,
-                    BTRFS_FT_FIFO => FileType::Fifo,
-                    BTRFS_FT_SYMLINK => FileType::Sym,
-                    _ => unreachable!(),
-                },
-                name: PathBuf::from(<OsStr as OsStrExt>::from_bytes(data)),
-            },
-        }
-    }
-}
-
-impl DirIndex {
-    pub(crate) fn from_c_struct(dir: btrfs_dir_item, data: &[u8]) -> Self {
-        match dir.type_ as u32 {
-            BTRFS_FT_XATTR => Self::Xattr {
-                location: DiskKey::from_c_struct(dir.location),
-                transid: le::U64::new(dir.transid),
-                name: data[..dir.name_len as usize].to_vec(),
-                value: data[dir.data_len as usize..].to_vec(),
-            },
-            _ => Self::File {
-                location: DiskKey::from_c_struct(dir.location),
-                transid: le::U64::new(dir.transid),
-                r#type: match dir.type_ as u32 {
-                    BTRFS_FT_REG_FILE => FileType::Reg,
-                    BTRFS_FT_DIR => FileType::Dir,
-                    BTRFS_FT_CHRDEV => FileType::ChrDev,
-                    BTRFS_FT_BLKDEV => FileType::BlkDev,
-                    BTRFS_FT_FIFO => FileType::Fifo,
-                    BTRFS_FT_SYMLINK => FileType::Sym,
-                    _ => unreachable!(),
-                },
-                name: PathBuf::from(<OsStr as OsStrExt>::from_bytes(data)),
-            },
-        }
+impl FileType {
+    pub(crate) fn from_dir_type(r#type: u8) -> Result<Self, ()> {
+        Ok(match r#type as u32 {
+            BTRFS_FT_REG_FILE => Self::Reg,
+            BTRFS_FT_DIR => Self::Dir,
+            BTRFS_FT_CHRDEV => Self::ChrDev,
+            BTRFS_FT_BLKDEV => Self::BlkDev,
+            BTRFS_FT_FIFO => Self::Fifo,
+            BTRFS_FT_SOCK => Self::Sock,
+            BTRFS_FT_SYMLINK => Self::Sym,
+            _ => return Err(()),
+        })
     }
 }
 
 impl FileExtentReg {
-    #[allow(non_upper_case_globals)]
-    pub(crate) fn from_c_struct(item: btrfs_file_extent_item) -> Self {
-        Self {
+    pub(crate) fn from_c_struct(item: btrfs_file_extent_item) -> Result<Self, ()> {
+        Ok(Self {
             generation: le::U64::new(item.generation),
             ram_bytes: le::U64::new(item.ram_bytes),
-            compression: match item.compression as u32 {
-                btrfs_compression_type_BTRFS_COMPRESS_NONE => Compression::None,
-                btrfs_compression_type_BTRFS_COMPRESS_LZO => Compression::Lzo,
-                btrfs_compression_type_BTRFS_COMPRESS_ZLIB => Compression::Zlib,
-                btrfs_compression_type_BTRFS_COMPRESS_ZSTD => Compression::Zstd,
-                _ => unreachable!(),
-            },
+            compression: compression(item.compression)?,
             disk_bytenr: le::U64::new(item.disk_bytenr),
             disk_num_bytes: le::U64::new(item.disk_num_bytes),
             offset: le::U64::new(item.offset),
             num_bytes: le::U64::new(item.num_bytes),
-        }
+        })
     }
 }
 
-impl FileExtentInline {
-    #[allow(non_upper_case_globals)]
-    pub(crate) fn from_c_struct_and_data(item: btrfs_file_extent_item, data: &[u8]) -> Self {
-        Self {
-            generation: le::U64::new(item.generation),
-            ram_bytes: le::U64::new(item.ram_bytes),
-            compression: match item.compression as u32 {
-                btrfs_compression_type_BTRFS_COMPRESS_NONE => Compression::None,
-                btrfs_compression_type_BTRFS_COMPRESS_LZO => Compression::Lzo,
-                btrfs_compression_type_BTRFS_COMPRESS_ZLIB => Compression::Zlib,
-                btrfs_compression_type_BTRFS_COMPRESS_ZSTD => Compression::Zstd,
-                _ => unreachable!(),
-            },
-            data: data.to_vec(),
-        }
-    }
+#[allow(non_upper_case_globals)]
+pub(crate) fn compression(compression: u8) -> Result<Compression, ()> {
+    Ok(match compression as u32 {
+        btrfs_compression_type_BTRFS_COMPRESS_NONE => Compression::None,
+        btrfs_compression_type_BTRFS_COMPRESS_LZO => Compression::Lzo,
+        btrfs_compression_type_BTRFS_COMPRESS_ZLIB => Compression::Zlib,
+        btrfs_compression_type_BTRFS_COMPRESS_ZSTD => Compression::Zstd,
+        _ => return Err(()),
+    })
 }
 
 impl FreeSpaceHeader {
@@ -381,3 +1041,95 @@ impl FreeSpaceHeader {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn free_ranges() {
+        let sectorsize = 4096;
+        let bitmap = FreeSpaceBitmap {
+            start: 1 << 20,
+            length: 16 * sectorsize,
+            sectorsize: sectorsize as u32,
+            // sectors 1-2, 7-8 and 15 are free
+            bitmap: vec![0b1000_0110, 0b1000_0001],
+        };
+
+        let sector = |n: u64| bitmap.start + n * sectorsize;
+
+        assert!(!bitmap.is_free(0));
+        assert!(bitmap.is_free(1));
+        assert!(bitmap.is_free(8));
+        assert!(!bitmap.is_free(16));
+        assert_eq!(
+            bitmap.free_ranges().collect::<Vec<_>>(),
+            [
+                sector(1)..sector(3),
+                sector(7)..sector(9),
+                sector(15)..sector(16)
+            ]
+        );
+    }
+
+    #[test]
+    fn balance_args_flags() {
+        // the item btrfs balance start -dlimit=1..5,stripes=2..4 leaves behind
+        // when it's paused
+        let mut data = [0u8; mem::size_of::<btrfs_balance_item>()];
+        let put = |data: &mut [u8], offset: usize, value: &[u8]| {
+            data[offset..offset + value.len()].copy_from_slice(value)
+        };
+        let args = mem::offset_of!(btrfs_balance_item, data);
+
+        // BTRFS_BALANCE_DATA
+        put(&mut data, 0, &1u64.to_le_bytes());
+        // BTRFS_BALANCE_ARGS_LIMIT_RANGE | BTRFS_BALANCE_ARGS_STRIPES_RANGE
+        put(
+            &mut data,
+            args + mem::offset_of!(btrfs_disk_balance_args, flags),
+            &0xc0u64.to_le_bytes(),
+        );
+        put(
+            &mut data,
+            args + mem::offset_of!(btrfs_disk_balance_args, __bindgen_anon_2),
+            &[1u32.to_le_bytes(), 5u32.to_le_bytes()].concat(),
+        );
+        put(
+            &mut data,
+            args + mem::offset_of!(btrfs_disk_balance_args, stripes_min),
+            &[2u32.to_le_bytes(), 4u32.to_le_bytes()].concat(),
+        );
+
+        let balance = Balance::from_c_struct(unsafe {
+            data.as_ptr().cast::<btrfs_balance_item>().read_unaligned()
+        })
+        .unwrap();
+
+        assert_eq!(balance.flags, BalanceFlag::DATA);
+        assert_eq!(
+            balance.data.flags,
+            BalanceArgsFlag::LIMIT_RANGE | BalanceArgsFlag::STRIPES_RANGE
+        );
+        assert_eq!(balance.data.limit_range(), (1, 5));
+        assert_eq!(
+            (
+                balance.data.stripes_min.get(),
+                balance.data.stripes_max.get()
+            ),
+            (2, 4)
+        );
+        assert!(balance.meta.flags.is_empty());
+
+        // -dusage=10..90
+        let mut args: btrfs_disk_balance_args = unsafe { mem::zeroed() };
+        args.flags = 1 << 10;
+        args.__bindgen_anon_1.usage = 90 << 32 | 10;
+
+        assert_eq!(
+            BalanceArgs::from_c_struct(args).unwrap().usage_range(),
+            (10, 90)
+        );
+    }
+}
@@ -0,0 +1,764 @@
+use crate::item::{
+    self, compression, sector_is_free, BlockGroup, BlockGroupFlag, DevExtent, DevItem, DevReplace,
+    DevStats, DirLog, DiskKey, ExtentDataRef, ExtentFlag, ExtentInlineRef, FileExtentReg, FileType,
+    FreeRanges, FreeSpaceExtent, FreeSpaceHeader, FreeSpaceInfo, Inode, Orphan, QgroupInfo,
+    QgroupLimit, QgroupRelation, QgroupStatus, Root, SharedBlockRef, SharedDataRef, Stripe,
+    TreeBlockInfo, TreeBlockRef,
+};
+use crate::{le, tree_search::Item, Compression, Uuid};
+use core::{mem, slice};
+use std::{ffi::OsStr, ops::Range, os::unix::ffi::OsStrExt, path::PathBuf};
+
+use btrfs_sys::{
+    btrfs_chunk, btrfs_dir_item, btrfs_extent_item, btrfs_file_extent_item, btrfs_inode_extref,
+    btrfs_inode_ref, btrfs_root_ref, btrfs_stripe, btrfs_tree_block_info, BTRFS_FT_XATTR,
+};
+
+// the same items as tree_search::Item, but names and data borrow the ioctl
+// buffer instead of being copied out of it
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone, Debug)]
+pub enum ItemRef<'buf> {
+    Root(Root),
+    RootRef(RootRef<'buf>),
+    RootBackRef(RootRef<'buf>),
+    FileExtentReg(FileExtentReg),
+    FileExtentInline(FileExtentInline<'buf>),
+    DirItem(DirItem<'buf>),
+    DirIndex(DirIndex<'buf>),
+    Inode(Inode),
+    InodeRef(InodeRefs<'buf>),
+    InodeExtref(InodeExtrefs<'buf>),
+    FreeSpaceHeader(FreeSpaceHeader),
+    BlockGroup(BlockGroup),
+    Chunk(Chunk<'buf>),
+    DevItem(DevItem),
+    DevExtent(DevExtent),
+    Extent(Extent<'buf>),
+    Metadata(Extent<'buf>),
+    TreeBlockRef(TreeBlockRef),
+    SharedBlockRef(SharedBlockRef),
+    ExtentDataRef(ExtentDataRef),
+    SharedDataRef(SharedDataRef),
+    Csum(Csum<'buf>),
+    FreeSpaceInfo(FreeSpaceInfo),
+    FreeSpaceExtent(FreeSpaceExtent),
+    FreeSpaceBitmap(FreeSpaceBitmap<'buf>),
+    QgroupStatus(QgroupStatus),
+    QgroupInfo(QgroupInfo),
+    QgroupLimit(QgroupLimit),
+    QgroupRelation(QgroupRelation),
+    UuidSubvol(UuidItem<'buf>),
+    UuidReceivedSubvol(UuidItem<'buf>),
+    Orphan(Orphan),
+    DirLogItem(DirLog),
+    DirLogIndex(DirLog),
+    Balance(item::Balance),
+    DevStats(DevStats),
+    DevReplace(DevReplace),
+    VerityDesc(VerityDescItem<'buf>),
+    VerityMerkle(VerityMerkle<'buf>),
+    Unknown { r#type: u8, data: &'buf [u8] },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RootRef<'buf> {
+    pub dirid: le::U64,
+    pub sequence: le::U64,
+    pub name: &'buf OsStr,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FileExtentInline<'buf> {
+    pub generation: le::U64,
+    pub ram_bytes: le::U64,
+    pub compression: Compression,
+    pub data: &'buf [u8],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DirItem<'buf> {
+    Xattr {
+        location: DiskKey,
+        transid: le::U64,
+        name: &'buf [u8],
+        value: &'buf [u8],
+    },
+    File {
+        location: DiskKey,
+        transid: le::U64,
+        name: &'buf OsStr,
+        r#type: FileType,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DirIndex<'buf> {
+    Xattr {
+        location: DiskKey,
+        transid: le::U64,
+        name: &'buf [u8],
+        value: &'buf [u8],
+    },
+    File {
+        location: DiskKey,
+        transid: le::U64,
+        name: &'buf OsStr,
+        r#type: FileType,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InodeRef<'buf> {
+    pub index: le::U64,
+    pub name: &'buf OsStr,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InodeExtref<'buf> {
+    pub parent: le::U64,
+    pub index: le::U64,
+    pub name: &'buf OsStr,
+}
+
+// the entries of an inode ref item, checked when the item is decoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InodeRefs<'buf> {
+    data: &'buf [u8],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct InodeExtrefs<'buf> {
+    data: &'buf [u8],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Extent<'buf> {
+    pub refs: le::U64,
+    pub generation: le::U64,
+    pub flags: ExtentFlag,
+    pub tree_block_info: Option<TreeBlockInfo>,
+    pub inline_refs: ExtentInlineRefs<'buf>,
+}
+
+// the inline refs of an extent item, checked when the item is decoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ExtentInlineRefs<'buf> {
+    data: &'buf [u8],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Csum<'buf> {
+    pub start: le::U64,
+    csum_size: usize,
+    data: &'buf [u8],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Chunk<'buf> {
+    pub length: le::U64,
+    pub owner: le::U64,
+    pub stripe_len: le::U64,
+    pub r#type: BlockGroupFlag,
+    pub io_align: le::U32,
+    pub io_width: le::U32,
+    pub sector_size: le::U32,
+    pub num_stripes: le::U16,
+    pub sub_stripes: le::U16,
+    pub stripes: Stripes<'buf>,
+}
+
+// the stripes of a chunk item, checked when the item is decoded
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Stripes<'buf> {
+    data: &'buf [u8],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FreeSpaceBitmap<'buf> {
+    pub start: u64,
+    pub length: u64,
+    pub sectorsize: u32,
+    pub bitmap: &'buf [u8],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct UuidItem<'buf> {
+    pub uuid: Uuid,
+    pub subvol_ids: SubvolIds<'buf>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SubvolIds<'buf> {
+    data: &'buf [u8],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum VerityDescItem<'buf> {
+    Info { size: le::U64, encryption: u8 },
+    Data { offset: u64, data: &'buf [u8] },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VerityMerkle<'buf> {
+    pub offset: u64,
+    pub data: &'buf [u8],
+}
+
+impl ItemRef<'_> {
+    pub fn to_owned(&self) -> Item {
+        match self {
+            Self::Root(root) => Item::Root(*root),
+            Self::RootRef(root_ref) => Item::RootRef(root_ref.to_owned()),
+            Self::RootBackRef(root_ref) => Item::RootBackRef(root_ref.to_owned()),
+            Self::FileExtentReg(extent) => Item::FileExtentReg(*extent),
+            Self::FileExtentInline(extent) => Item::FileExtentInline(extent.to_owned()),
+            Self::DirItem(dir) => Item::DirItem(dir.to_owned()),
+            Self::DirIndex(dir) => Item::DirIndex(dir.to_owned()),
+            Self::Inode(inode) => Item::Inode(*inode),
+            Self::InodeRef(refs) => Item::InodeRef(refs.map(|r| r.to_owned()).collect()),
+            Self::InodeExtref(extrefs) => {
+                Item::InodeExtref(extrefs.map(|extref| extref.to_owned()).collect())
+            }
+            Self::FreeSpaceHeader(header) => Item::FreeSpaceHeader(*header),
+            Self::BlockGroup(block_group) => Item::BlockGroup(*block_group),
+            Self::Chunk(chunk) => Item::Chunk(chunk.to_owned()),
+            Self::DevItem(dev_item) => Item::DevItem(*dev_item),
+            Self::DevExtent(dev_extent) => Item::DevExtent(*dev_extent),
+            Self::Extent(extent) => Item::Extent(extent.to_owned()),
+            Self::Metadata(extent) => Item::Metadata(extent.to_owned()),
+            Self::TreeBlockRef(tree_block_ref) => Item::TreeBlockRef(*tree_block_ref),
+            Self::SharedBlockRef(shared_block_ref) => Item::SharedBlockRef(*shared_block_ref),
+            Self::ExtentDataRef(extent_data_ref) => Item::ExtentDataRef(*extent_data_ref),
+            Self::SharedDataRef(shared_data_ref) => Item::SharedDataRef(*shared_data_ref),
+            Self::Csum(csum) => Item::Csum(csum.to_owned()),
+            Self::FreeSpaceInfo(info) => Item::FreeSpaceInfo(*info),
+            Self::FreeSpaceExtent(extent) => Item::FreeSpaceExtent(*extent),
+            Self::FreeSpaceBitmap(bitmap) => Item::FreeSpaceBitmap(bitmap.to_owned()),
+            Self::QgroupStatus(status) => Item::QgroupStatus(*status),
+            Self::QgroupInfo(info) => Item::QgroupInfo(*info),
+            Self::QgroupLimit(limit) => Item::QgroupLimit(*limit),
+            Self::QgroupRelation(relation) => Item::QgroupRelation(*relation),
+            Self::UuidSubvol(uuid_item) => Item::UuidSubvol(uuid_item.to_owned()),
+            Self::UuidReceivedSubvol(uuid_item) => Item::UuidReceivedSubvol(uuid_item.to_owned()),
+            Self::Orphan(orphan) => Item::Orphan(*orphan),
+            Self::DirLogItem(dir_log) => Item::DirLogItem(*dir_log),
+            Self::DirLogIndex(dir_log) => Item::DirLogIndex(*dir_log),
+            Self::Balance(balance) => Item::Balance(*balance),
+            Self::DevStats(stats) => Item::DevStats(*stats),
+            Self::DevReplace(dev_replace) => Item::DevReplace(*dev_replace),
+            Self::VerityDesc(desc) => Item::VerityDesc(desc.to_owned()),
+            Self::VerityMerkle(merkle) => Item::VerityMerkle(merkle.to_owned()),
+            Self::Unknown { r#type, data } => Item::Unknown {
+                r#type: *r#type,
+                data: data.to_vec(),
+            },
+        }
+    }
+}
+
+impl<'buf> RootRef<'buf> {
+    pub(crate) fn from_c_struct(root_ref: btrfs_root_ref, data: &'buf [u8]) -> Self {
+        Self {
+            dirid: le::U64::new(root_ref.dirid),
+            sequence: le::U64::new(root_ref.sequence),
+            name: <OsStr as OsStrExt>::from_bytes(data),
+        }
+    }
+
+    pub fn to_owned(&self) -> item::RootRef {
+        item::RootRef {
+            dirid: self.dirid,
+            sequence: self.sequence,
+            name: PathBuf::from(self.name),
+        }
+    }
+}
+
+impl<'buf> FileExtentInline<'buf> {
+    pub(crate) fn from_c_struct_and_data(
+        item: btrfs_file_extent_item,
+        data: &'buf [u8],
+    ) -> Result<Self, ()> {
+        Ok(Self {
+            generation: le::U64::new(item.generation),
+            ram_bytes: le::U64::new(item.ram_bytes),
+            compression: compression(item.compression)?,
+            data,
+        })
+    }
+
+    pub fn to_owned(&self) -> item::FileExtentInline {
+        item::FileExtentInline {
+            generation: self.generation,
+            ram_bytes: self.ram_bytes,
+            compression: self.compression,
+            data: self.data.to_vec(),
+        }
+    }
+}
+
+impl<'buf> DirItem<'buf> {
+    // data holds the name followed by the xattr value, if there is one
+    pub(crate) fn from_c_struct(dir: btrfs_dir_item, data: &'buf [u8]) -> Result<Self, ()> {
+        let (name, value) = data.split_at(dir.name_len as usize);
+
+        Ok(match dir.type_ as u32 {
+            BTRFS_FT_XATTR => Self::Xattr {
+                location: DiskKey::from_c_struct(dir.location),
+                transid: le::U64::new(dir.transid),
+                name,
+                value,
+            },
+            _ => Self::File {
+                location: DiskKey::from_c_struct(dir.location),
+                transid: le::U64::new(dir.transid),
+                r#type: FileType::from_dir_type(dir.type_)?,
+                name: <OsStr as OsStrExt>::from_bytes(name),
+            },
+        })
+    }
+
+    pub fn to_owned(&self) -> item::DirItem {
+        match *self {
+            Self::Xattr {
+                location,
+                transid,
+                name,
+                value,
+            } => item::DirItem::Xattr {
+                location,
+                transid,
+                name: name.to_vec(),
+                value: value.to_vec(),
+            },
+            Self::File {
+                location,
+                transid,
+                name,
+                r#type,
+            } => item::DirItem::File {
+                location,
+                transid,
+                name: PathBuf::from(name),
+                r#type,
+            },
+        }
+    }
+}
+
+impl<'buf> DirIndex<'buf> {
+    // data holds the name followed by the xattr value, if there is one
+    pub(crate) fn from_c_struct(dir: btrfs_dir_item, data: &'buf [u8]) -> Result<Self, ()> {
+        let (name, value) = data.split_at(dir.name_len as usize);
+
+        Ok(match dir.type_ as u32 {
+            BTRFS_FT_XATTR => Self::Xattr {
+                location: DiskKey::from_c_struct(dir.location),
+                transid: le::U64::new(dir.transid),
+                name,
+                value,
+            },
+            _ => Self::File {
+                location: DiskKey::from_c_struct(dir.location),
+                transid: le::U64::new(dir.transid),
+                r#type: FileType::from_dir_type(dir.type_)?,
+                name: <OsStr as OsStrExt>::from_bytes(name),
+            },
+        })
+    }
+
+    pub fn to_owned(&self) -> item::DirIndex {
+        match *self {
+            Self::Xattr {
+                location,
+                transid,
+                name,
+                value,
+            } => item::DirIndex::Xattr {
+                location,
+                transid,
+                name: name.to_vec(),
+                value: value.to_vec(),
+            },
+            Self::File {
+                location,
+                transid,
+                name,
+                r#type,
+            } => item::DirIndex::File {
+                location,
+                transid,
+                name: PathBuf::from(name),
+                r#type,
+            },
+        }
+    }
+}
+
+impl InodeRef<'_> {
+    pub fn to_owned(&self) -> item::InodeRef {
+        item::InodeRef {
+            index: self.index,
+            name: PathBuf::from(self.name),
+        }
+    }
+}
+
+impl InodeExtref<'_> {
+    pub fn to_owned(&self) -> item::InodeExtref {
+        item::InodeExtref {
+            parent: self.parent,
+            index: self.index,
+            name: PathBuf::from(self.name),
+        }
+    }
+}
+
+impl<'buf> InodeRefs<'buf> {
+    // an inode ref item holds one entry for each name the inode has in the
+    // directory from the key offset
+    pub(crate) fn from_data(data: &'buf [u8]) -> Result<Self, ()> {
+        let mut rest = data;
+
+        while !rest.is_empty() {
+            rest = &rest[next_inode_ref(rest)?.1..];
+        }
+
+        Ok(Self { data })
+    }
+}
+
+impl<'buf> Iterator for InodeRefs<'buf> {
+    type Item = InodeRef<'buf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (inode_ref, len) = next_inode_ref(self.data).ok()?;
+
+        self.data = &self.data[len..];
+
+        Some(inode_ref)
+    }
+}
+
+fn next_inode_ref(data: &[u8]) -> Result<(InodeRef<'_>, usize), ()> {
+    let inode_ref = unsafe {
+        data.get(..mem::size_of::<btrfs_inode_ref>())
+            .ok_or(())?
+            .as_ptr()
+            .cast::<btrfs_inode_ref>()
+            .read_unaligned()
+    };
+
+    let end = mem::size_of::<btrfs_inode_ref>() + inode_ref.name_len as usize;
+    let name = data.get(mem::size_of::<btrfs_inode_ref>()..end).ok_or(())?;
+
+    Ok((
+        InodeRef {
+            index: le::U64::new(inode_ref.index),
+            name: <OsStr as OsStrExt>::from_bytes(name),
+        },
+        end,
+    ))
+}
+
+impl<'buf> InodeExtrefs<'buf> {
+    // extrefs whose (parent, name) hashes collide are packed into the same item
+    pub(crate) fn from_data(data: &'buf [u8]) -> Result<Self, ()> {
+        let mut rest = data;
+
+        while !rest.is_empty() {
+            rest = &rest[next_inode_extref(rest)?.1..];
+        }
+
+        Ok(Self { data })
+    }
+}
+
+impl<'buf> Iterator for InodeExtrefs<'buf> {
+    type Item = InodeExtref<'buf>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (extref, len) = next_inode_extref(self.data).ok()?;
+
+        self.data = &self.data[len..];
+
+        Some(extref)
+    }
+}
+
+fn next_inode_extref(data: &[u8]) -> Result<(InodeExtref<'_>, usize), ()> {
+    let extref = unsafe {
+        data.get(..mem::size_of::<btrfs_inode_extref>())
+            .ok_or(())?
+            .as_ptr()
+            .cast::<btrfs_inode_extref>()
+            .read_unaligned()
+    };
+
+    let end = mem::size_of::<btrfs_inode_extref>() + extref.name_len as usize;
+    let name = data
+        .get(mem::size_of::<btrfs_inode_extref>()..end)
+        .ok_or(())?;
+
+    Ok((
+        InodeExtref {
+            parent: le::U64::new(extref.parent_objectid),
+            index: le::U64::new(extref.index),
+            name: <OsStr as OsStrExt>::from_bytes(name),
+        },
+        end,
+    ))
+}
+
+impl<'buf> Extent<'buf> {
+    pub(crate) fn from_c_struct(
+        extent: btrfs_extent_item,
+        data: &'buf [u8],
+        has_tree_block_info: bool,
+    ) -> Result<Self, ()> {
+        let flags = ExtentFlag::from_bits(extent.flags).ok_or(())?;
+
+        // metadata items store the level in the key offset instead of a tree block info
+        let (tree_block_info, data) =
+            if has_tree_block_info && flags.contains(ExtentFlag::TREE_BLOCK) {
+                let info = data
+                    .get(..mem::size_of::<btrfs_tree_block_info>())
+                    .ok_or(())?;
+
+                (
+                    Some(TreeBlockInfo::from_c_struct(unsafe {
+                        info.as_ptr()
+                            .cast::<btrfs_tree_block_info>()
+                            .read_unaligned()
+                    })),
+                    &data[mem::size_of::<btrfs_tree_block_info>()..],
+                )
+            } else {
+                (None, data)
+            };
+
+        let mut rest = data;
+
+        while !rest.is_empty() {
+            rest = &rest[ExtentInlineRef::from_bytes(rest)?.1..];
+        }
+
+        Ok(Self {
+            refs: le::U64::new(extent.refs),
+            generation: le::U64::new(extent.generation),
+            flags,
+            tree_block_info,
+            inline_refs: ExtentInlineRefs { data },
+        })
+    }
+
+    pub fn to_owned(&self) -> item::Extent {
+        item::Extent {
+            refs: self.refs,
+            generation: self.generation,
+            flags: self.flags,
+            tree_block_info: self.tree_block_info,
+            inline_refs: self.inline_refs.collect(),
+        }
+    }
+}
+
+impl Iterator for ExtentInlineRefs<'_> {
+    type Item = ExtentInlineRef;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (inline_ref, len) = ExtentInlineRef::from_bytes(self.data).ok()?;
+
+        self.data = &self.data[len..];
+
+        Some(inline_ref)
+    }
+}
+
+impl<'buf> Csum<'buf> {
+    pub(crate) fn from_data(offset: u64, data: &'buf [u8], csum_size: usize) -> Result<Self, ()> {
+        if csum_size == 0 || !data.len().is_multiple_of(csum_size) {
+            return Err(());
+        }
+
+        Ok(Self {
+            start: le::U64::new(offset),
+            csum_size,
+            data,
+        })
+    }
+
+    pub fn sums(&self) -> slice::ChunksExact<'buf, u8> {
+        self.data.chunks_exact(self.csum_size)
+    }
+
+    pub fn to_owned(&self) -> item::Csum {
+        item::Csum {
+            start: self.start,
+            sums: self.sums().map(<[u8]>::to_vec).collect(),
+        }
+    }
+}
+
+impl<'buf> Chunk<'buf> {
+    pub(crate) fn from_c_struct(chunk: btrfs_chunk, data: &'buf [u8]) -> Result<Self, ()> {
+        let stripes = data
+            .get(..chunk.num_stripes as usize * mem::size_of::<btrfs_stripe>())
+            .ok_or(())?;
+
+        Ok(Self {
+            length: le::U64::new(chunk.length),
+            owner: le::U64::new(chunk.owner),
+            stripe_len: le::U64::new(chunk.stripe_len),
+            r#type: BlockGroupFlag::from_bits(chunk.type_).ok_or(())?,
+            io_align: le::U32::new(chunk.io_align),
+            io_width: le::U32::new(chunk.io_width),
+            sector_size: le::U32::new(chunk.sector_size),
+            num_stripes: le::U16::new(chunk.num_stripes),
+            sub_stripes: le::U16::new(chunk.sub_stripes),
+            stripes: Stripes { data: stripes },
+        })
+    }
+
+    pub fn to_owned(&self) -> item::Chunk {
+        item::Chunk {
+            length: self.length,
+            owner: self.owner,
+            stripe_len: self.stripe_len,
+            r#type: self.r#type,
+            io_align: self.io_align,
+            io_width: self.io_width,
+            sector_size: self.sector_size,
+            num_stripes: self.num_stripes,
+            sub_stripes: self.sub_stripes,
+            stripes: self.stripes.collect(),
+        }
+    }
+}
+
+impl Iterator for Stripes<'_> {
+    type Item = Stripe;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let stripe = self.data.get(..mem::size_of::<btrfs_stripe>())?;
+
+        self.data = &self.data[mem::size_of::<btrfs_stripe>()..];
+
+        Some(Stripe::from_c_struct(unsafe {
+            stripe.as_ptr().cast::<btrfs_stripe>().read_unaligned()
+        }))
+    }
+}
+
+impl<'buf> FreeSpaceBitmap<'buf> {
+    pub(crate) fn from_data(
+        start: u64,
+        length: u64,
+        sectorsize: u32,
+        data: &'buf [u8],
+    ) -> Result<Self, ()> {
+        if sectorsize == 0 {
+            return Err(());
+        }
+
+        Ok(Self {
+            start,
+            length,
+            sectorsize,
+            bitmap: data,
+        })
+    }
+
+    pub fn range(&self) -> Range<u64> {
+        self.start..self.start + self.length
+    }
+
+    pub fn is_free(&self, sector: u64) -> bool {
+        sector_is_free(self.bitmap, sector)
+    }
+
+    pub fn free_ranges(&self) -> FreeRanges<'buf> {
+        FreeRanges::new(self.start, self.length, self.sectorsize, self.bitmap)
+    }
+
+    pub fn to_owned(&self) -> item::FreeSpaceBitmap {
+        item::FreeSpaceBitmap {
+            start: self.start,
+            length: self.length,
+            sectorsize: self.sectorsize,
+            bitmap: self.bitmap.to_vec(),
+        }
+    }
+}
+
+impl<'buf> UuidItem<'buf> {
+    // the uuid is split over the key, the item is a list of subvolume ids
+    pub(crate) fn from_data(objectid: u64, offset: u64, data: &'buf [u8]) -> Result<Self, ()> {
+        if !data.len().is_multiple_of(mem::size_of::<u64>()) {
+            return Err(());
+        }
+
+        Ok(Self {
+            uuid: Uuid::from_key(objectid, offset),
+            subvol_ids: SubvolIds { data },
+        })
+    }
+
+    pub fn to_owned(&self) -> item::UuidItem {
+        item::UuidItem {
+            uuid: self.uuid,
+            subvol_ids: self.subvol_ids.collect(),
+        }
+    }
+}
+
+impl Iterator for SubvolIds<'_> {
+    type Item = le::U64;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let id = self.data.get(..mem::size_of::<u64>())?;
+
+        self.data = &self.data[mem::size_of::<u64>()..];
+
+        Some(le::U64::new(u64::from_le_bytes(id.try_into().unwrap())))
+    }
+}
+
+impl<'buf> VerityDescItem<'buf> {
+    // the item at offset 0 records the descriptor's size, the descriptor itself
+    // is stored in the items that follow starting at offset 1
+    pub(crate) fn from_data(offset: u64, data: &'buf [u8]) -> Result<Self, ()> {
+        Ok(match offset {
+            0 => Self::Info {
+                size: le::U64::new(u64::from_le_bytes(
+                    data.get(..8).ok_or(())?.try_into().unwrap(),
+                )),
+                encryption: *data.get(mem::size_of::<u64>() * 3).ok_or(())?,
+            },
+            _ => Self::Data {
+                offset: offset - 1,
+                data,
+            },
+        })
+    }
+
+    pub fn to_owned(&self) -> item::VerityDescItem {
+        match *self {
+            Self::Info { size, encryption } => item::VerityDescItem::Info { size, encryption },
+            Self::Data { offset, data } => item::VerityDescItem::Data {
+                offset,
+                data: data.to_vec(),
+            },
+        }
+    }
+}
+
+impl VerityMerkle<'_> {
+    pub fn to_owned(&self) -> item::VerityMerkle {
+        item::VerityMerkle {
+            offset: self.offset,
+            data: self.data.to_vec(),
+        }
+    }
+}
@@ -1,11 +1,19 @@
 #![allow(dead_code)]
 
+pub mod error;
+pub mod find_new;
+pub mod fs_info;
+pub mod inode_links;
 pub mod item;
+pub mod item_ref;
 pub mod le;
 pub mod logical_ino;
+mod subvolume;
 pub mod tree_search;
+pub mod uuid_tree;
+pub mod verity;
 
-use core::{ffi::CStr, mem, time};
+use core::{ffi::CStr, fmt, mem, time};
 use std::{
     ffi::OsStr,
     fs::File,
@@ -15,11 +23,22 @@ use std::{
 
 pub use btrfs_sys;
 use btrfs_sys::{
-    btrfs_ioctl_get_subvol_info_args, BTRFS_FIRST_FREE_OBJECTID, BTRFS_IOCTL_MAGIC, BTRFS_UUID_SIZE,
+    btrfs_ioctl_get_subvol_info_args, BTRFS_FIRST_FREE_OBJECTID, BTRFS_IOCTL_MAGIC,
+    BTRFS_QGROUP_LEVEL_SHIFT, BTRFS_UUID_SIZE,
 };
+pub use error::{Error, Ioctl};
+pub use find_new::{ChangedExtent, ChangedInode, Changes, FileExtent};
+pub use fs_info::{fs_info, CsumType, FsInfo};
+pub use inode_links::{inode_links, InodeLink};
 pub use logical_ino::LogicalIno;
 use nix::libc::BTRFS_SUPER_MAGIC;
-pub use tree_search::TreeSearch;
+pub use subvolume::{
+    default_subvolume, list_subvolumes, set_default_subvolume, SubvolEntry, SubvolFilter,
+    SubvolFlags, SubvolSpec,
+};
+pub use tree_search::{Key, KeyRange, KeyType, TreeSearch};
+pub use uuid_tree::{find_subvol_by_received_uuid, find_subvol_by_uuid};
+pub use verity::{verity_descriptor, verity_digest, VerityDescriptor, VerityDigest};
 
 const IOCTL_BUFF_SIZE: usize = 2usize.pow(16);
 
@@ -41,6 +60,9 @@ pub enum Compression {
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Uuid(pub [u8; BTRFS_UUID_SIZE as usize]);
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct QgroupId(pub u64);
+
 #[derive(Clone, Debug)]
 pub struct SubvolInfo {
     pub tree_id: u64,
@@ -48,7 +70,7 @@ pub struct SubvolInfo {
     pub parent_id: u64,
     pub dirid: u64,
     pub generation: u64,
-    pub flags: u64,
+    pub flags: SubvolFlags,
     pub uuid: Uuid,
     pub parent_uuid: Uuid,
     pub received_uuid: Uuid,
@@ -66,7 +88,7 @@ pub struct SubvolInfo {
 pub struct Subvolume<'a>(&'a File);
 
 impl<'a> Subvolume<'a> {
-    pub fn new(file: &'a File) -> Result<Option<Self>, nix::Error> {
+    pub fn new(file: &'a File) -> Result<Option<Self>, Error> {
         match is_subvol(file) {
             Ok(true) => Ok(Some(Self(file))),
             Ok(false) => Ok(None),
@@ -74,10 +96,11 @@ impl<'a> Subvolume<'a> {
         }
     }
 
-    pub fn info(&self) -> nix::Result<SubvolInfo> {
+    pub fn info(&self) -> Result<SubvolInfo, Error> {
         let mut args: btrfs_ioctl_get_subvol_info_args = unsafe { mem::zeroed() };
 
-        unsafe { btrfs_get_subvol_info(self.0.as_raw_fd(), &mut args as *mut _)? };
+        unsafe { btrfs_get_subvol_info(self.0.as_raw_fd(), &mut args as *mut _) }
+            .map_err(Error::ioctl(Ioctl::GetSubvolInfo))?;
 
         Ok(SubvolInfo::from_c_struct(args))
     }
@@ -85,6 +108,12 @@ impl<'a> Subvolume<'a> {
     pub fn as_file(&self) -> &File {
         self.0
     }
+
+    // like btrfs subvolume find-new, the inodes and file extents written after
+    // the given generation
+    pub fn changed_since(&self, generation: u64) -> Result<Changes, Error> {
+        find_new::changed_since(self, generation)
+    }
 }
 
 impl SubvolInfo {
@@ -99,7 +128,7 @@ impl SubvolInfo {
             },
             dirid: info.dirid,
             generation: info.generation,
-            flags: info.flags,
+            flags: SubvolFlags::from_root_flags(info.flags),
             uuid: Uuid(info.uuid),
             parent_uuid: Uuid(info.parent_uuid),
             received_uuid: Uuid(info.received_uuid),
@@ -119,12 +148,54 @@ impl SubvolInfo {
     }
 }
 
-fn is_subvol(file: &File) -> nix::Result<bool> {
+impl Uuid {
+    // the uuid tree splits uuids across the objectid and offset of its keys
+    pub(crate) fn from_key(objectid: u64, offset: u64) -> Self {
+        let mut uuid = [0; BTRFS_UUID_SIZE as usize];
+
+        uuid[..8].copy_from_slice(&objectid.to_le_bytes());
+        uuid[8..].copy_from_slice(&offset.to_le_bytes());
+
+        Self(uuid)
+    }
+
+    pub(crate) fn to_key(self) -> (u64, u64) {
+        (
+            u64::from_le_bytes(self.0[..8].try_into().unwrap()),
+            u64::from_le_bytes(self.0[8..].try_into().unwrap()),
+        )
+    }
+}
+
+impl QgroupId {
+    pub fn new(level: u16, subvolid: u64) -> Self {
+        Self((u64::from(level) << BTRFS_QGROUP_LEVEL_SHIFT) | subvolid)
+    }
+
+    pub fn level(self) -> u16 {
+        (self.0 >> BTRFS_QGROUP_LEVEL_SHIFT) as u16
+    }
+
+    pub fn subvolid(self) -> u64 {
+        self.0 & ((1 << BTRFS_QGROUP_LEVEL_SHIFT) - 1)
+    }
+}
+
+impl fmt::Display for QgroupId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.level(), self.subvolid())
+    }
+}
+
+fn is_subvol(file: &File) -> Result<bool, Error> {
     let statfs = nix::sys::statfs::fstatfs(file)?;
     let stat = nix::sys::stat::fstat(file.as_raw_fd())?;
 
-    Ok(statfs.filesystem_type().0 == BTRFS_SUPER_MAGIC
-        && stat.st_ino == BTRFS_FIRST_FREE_OBJECTID as u64
+    if statfs.filesystem_type().0 != BTRFS_SUPER_MAGIC {
+        return Err(Error::NotBtrfs);
+    }
+
+    Ok(stat.st_ino == BTRFS_FIRST_FREE_OBJECTID as u64
         && stat.st_mode & nix::sys::stat::SFlag::S_IFMT.bits()
             == nix::sys::stat::SFlag::S_IFDIR.bits())
 }
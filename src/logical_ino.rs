@@ -1,7 +1,16 @@
-use crate::IOCTL_BUFF_SIZE;
-use btrfs_sys::{btrfs_data_container, btrfs_ioctl_logical_ino_args, BTRFS_IOCTL_MAGIC};
+use crate::{Error, Ioctl, IOCTL_BUFF_SIZE};
+use btrfs_sys::{
+    btrfs_data_container, btrfs_ioctl_logical_ino_args, BTRFS_IOCTL_MAGIC,
+    BTRFS_LOGICAL_INO_ARGS_IGNORE_OFFSET,
+};
+use core::mem;
+use nix::errno::Errno;
 use std::{fs::File, os::fd::AsRawFd};
 
+// the kernel won't fill more than this no matter how big the buffer is
+const LOGICAL_INO_MAX_SIZE: usize = 16 * 1024 * 1024;
+const LOGICAL_INO_V1_MAX_SIZE: usize = 64 * 1024;
+
 nix::ioctl_readwrite!(
     btrfs_logical_ino,
     BTRFS_IOCTL_MAGIC,
@@ -9,35 +18,24 @@ nix::ioctl_readwrite!(
     btrfs_ioctl_logical_ino_args
 );
 
-#[repr(C)]
-#[derive(Clone, Copy, Debug)]
-struct Container {
-    bytes_left: u32,
-    bytes_missing: u32,
-    elem_cnt: u32,
-    elem_missed: u32,
-    buff: [u64; IOCTL_BUFF_SIZE],
-}
+nix::ioctl_readwrite!(
+    btrfs_logical_ino_v2,
+    BTRFS_IOCTL_MAGIC,
+    59,
+    btrfs_ioctl_logical_ino_args
+);
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub struct LogicalIno<'a> {
     file: &'a File,
     bytenr: u64,
-    container: Option<Container>,
+    size: usize,
+    // btrfs_data_container followed by (inum, offset, root) triples
+    container: Option<Vec<u64>>,
     bp: usize,
     ignore_offset: bool,
-}
-
-impl Default for Container {
-    fn default() -> Self {
-        Self {
-            bytes_left: Default::default(),
-            bytes_missing: Default::default(),
-            elem_cnt: Default::default(),
-            elem_missed: Default::default(),
-            buff: [0; IOCTL_BUFF_SIZE],
-        }
-    }
+    // set once the kernel turned out not to know about v2
+    v1: bool,
 }
 
 impl<'a> LogicalIno<'a> {
@@ -45,51 +43,110 @@ impl<'a> LogicalIno<'a> {
         Self {
             file,
             bytenr,
+            size: IOCTL_BUFF_SIZE,
             container: None,
             bp: 0,
             ignore_offset,
+            v1: false,
         }
     }
-}
 
-impl Iterator for LogicalIno<'_> {
-    type Item = Result<(u64, u64, u64), nix::Error>;
+    // the buffer still grows on its own when the results don't fit
+    pub fn with_buffer_size(mut self, size: usize) -> Self {
+        self.size = size.clamp(mem::size_of::<btrfs_data_container>(), LOGICAL_INO_MAX_SIZE);
+        self
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.container.is_none() {
-            let mut container = Container::default();
+    fn resolve(&mut self) -> Result<Vec<u64>, Error> {
+        loop {
+            let mut container = vec![0u64; self.size.div_ceil(mem::size_of::<u64>())];
 
             let mut args = btrfs_ioctl_logical_ino_args {
                 logical: self.bytenr,
-                size: IOCTL_BUFF_SIZE as u64,
+                size: self.size as u64,
                 reserved: Default::default(),
-                flags: self.ignore_offset as u64,
-                inodes: (&mut container as *mut Container)
-                    .cast::<btrfs_data_container>()
-                    .addr() as u64,
+                flags: match self.ignore_offset {
+                    true => BTRFS_LOGICAL_INO_ARGS_IGNORE_OFFSET as u64,
+                    false => 0,
+                },
+                inodes: container.as_mut_ptr().addr() as u64,
             };
 
-            match unsafe { btrfs_logical_ino(self.file.as_raw_fd(), &mut args as *mut _) } {
-                Ok(_) => (),
-                Err(e) => return Some(Err(e)),
+            let result = match self.v1 {
+                true => unsafe { btrfs_logical_ino(self.file.as_raw_fd(), &mut args as *mut _) }
+                    .map_err(Error::ioctl(Ioctl::LogicalIno)),
+                false => {
+                    unsafe { btrfs_logical_ino_v2(self.file.as_raw_fd(), &mut args as *mut _) }
+                        .map_err(Error::ioctl(Ioctl::LogicalInoV2))
+                }
+            };
+
+            match result {
+                Ok(_) => {}
+                // v2 needs linux 4.15. v1 caps the buffer at 64 KiB and has no
+                // flags, so it can't stand in when ignore_offset was asked for
+                Err(Error::Ioctl {
+                    errno: Errno::ENOTTY,
+                    ..
+                }) if !self.v1 && !self.ignore_offset => {
+                    self.v1 = true;
+                    self.size = self.size.min(LOGICAL_INO_V1_MAX_SIZE);
+                    continue;
+                }
+                Err(e) => return Err(e),
             }
 
-            self.container = Some(container);
+            let header = unsafe { container.as_ptr().cast::<btrfs_data_container>().read() };
+
+            if header.bytes_missing == 0 {
+                return Ok(container);
+            }
+
+            // bytes_missing is how much more the kernel needed for the rest
+            // of the results
+            let max_size = match self.v1 {
+                true => LOGICAL_INO_V1_MAX_SIZE,
+                false => LOGICAL_INO_MAX_SIZE,
+            };
+
+            if self.size >= max_size {
+                return Err(Error::BufferTooSmall {
+                    key: None,
+                    size: self.size,
+                });
+            }
+
+            self.size = (self.size + header.bytes_missing as usize).min(max_size);
         }
+    }
+}
 
-        let container = self.container.unwrap();
+impl Iterator for LogicalIno<'_> {
+    type Item = Result<(u64, u64, u64), Error>;
 
-        if container.elem_cnt > 0 {
-            let inum = container.buff[self.bp];
-            let offset = container.buff[self.bp + 1];
-            let root = container.buff[self.bp + 2];
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.container.is_none() {
+            match self.resolve() {
+                Ok(container) => self.container = Some(container),
+                Err(e) => return Some(Err(e)),
+            }
+        }
 
-            self.container.as_mut().unwrap().elem_cnt -= 1;
-            self.bp += 3;
+        let container = self.container.as_ref().unwrap();
+        let header = unsafe { container.as_ptr().cast::<btrfs_data_container>().read() };
+        let values = &container[mem::size_of::<btrfs_data_container>() / mem::size_of::<u64>()..];
 
-            Some(Ok((inum, offset, root)))
-        } else {
-            None
+        // elem_cnt counts the u64s, not the triples
+        if self.bp >= header.elem_cnt as usize {
+            return None;
         }
+
+        let &[inum, offset, root] = values.get(self.bp..self.bp + 3)? else {
+            return None;
+        };
+
+        self.bp += 3;
+
+        Some(Ok((inum, offset, root)))
     }
 }
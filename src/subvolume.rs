@@ -0,0 +1,489 @@
+use crate::{
+    inode_links::inode_path,
+    item::{DirItem, Root, RootRef},
+    tree_search::{Item, Key, KeyRange, KeyType, Tree},
+    Error, Ioctl, QgroupId, Subvolume, TreeSearch, Uuid,
+};
+use bitflags::bitflags;
+use btrfs_sys::{
+    btrfs_ioctl_received_subvol_args, btrfs_ioctl_vol_args, btrfs_ioctl_vol_args_v2,
+    btrfs_qgroup_inherit, BTRFS_FIRST_FREE_OBJECTID, BTRFS_FS_TREE_OBJECTID, BTRFS_IOCTL_MAGIC,
+    BTRFS_LAST_FREE_OBJECTID, BTRFS_ORPHAN_OBJECTID, BTRFS_ROOT_SUBVOL_RDONLY,
+    BTRFS_ROOT_TREE_DIR_OBJECTID, BTRFS_SUBVOL_NAME_MAX, BTRFS_SUBVOL_QGROUP_INHERIT,
+    BTRFS_SUBVOL_RDONLY, BTRFS_SUBVOL_SPEC_BY_ID, BTRFS_UUID_SIZE,
+};
+use core::{ffi::c_char, mem, time::Duration};
+use nix::{
+    errno::Errno,
+    fcntl::{self, OFlag},
+    sys::stat::Mode,
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsStr,
+    fs::File,
+    os::{
+        fd::{AsRawFd, FromRawFd},
+        unix::ffi::OsStrExt,
+    },
+    path::PathBuf,
+    thread,
+    time::Instant,
+};
+
+// how often to check whether the cleaner is done with a deleted subvolume
+const CLEANER_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+nix::ioctl_write_ptr!(
+    btrfs_snap_destroy,
+    BTRFS_IOCTL_MAGIC,
+    15,
+    btrfs_ioctl_vol_args
+);
+
+nix::ioctl_write_ptr!(
+    btrfs_snap_create_v2,
+    BTRFS_IOCTL_MAGIC,
+    23,
+    btrfs_ioctl_vol_args_v2
+);
+
+nix::ioctl_write_ptr!(
+    btrfs_subvol_create_v2,
+    BTRFS_IOCTL_MAGIC,
+    24,
+    btrfs_ioctl_vol_args_v2
+);
+
+nix::ioctl_write_ptr!(
+    btrfs_snap_destroy_v2,
+    BTRFS_IOCTL_MAGIC,
+    63,
+    btrfs_ioctl_vol_args_v2
+);
+
+nix::ioctl_write_ptr!(btrfs_default_subvol, BTRFS_IOCTL_MAGIC, 19, u64);
+
+nix::ioctl_read!(btrfs_subvol_getflags, BTRFS_IOCTL_MAGIC, 25, u64);
+
+nix::ioctl_write_ptr!(btrfs_subvol_setflags, BTRFS_IOCTL_MAGIC, 26, u64);
+
+nix::ioctl_readwrite!(
+    btrfs_set_received_subvol,
+    BTRFS_IOCTL_MAGIC,
+    37,
+    btrfs_ioctl_received_subvol_args
+);
+
+bitflags! {
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct SubvolFlags: u64 {
+        const RDONLY = BTRFS_SUBVOL_RDONLY as u64;
+    }
+
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SubvolEntry {
+    pub id: u64,
+    pub parent_id: u64,
+    // relative to the top level subvolume
+    pub path: PathBuf,
+    pub generation: u64,
+    pub uuid: Uuid,
+    pub parent_uuid: Uuid,
+    pub received_uuid: Uuid,
+    pub read_only: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SubvolFilter {
+    pub snapshots_only: bool,
+    pub read_only: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SubvolSpec<'a> {
+    // a subvolume directly inside the directory
+    Name(&'a OsStr),
+    // any subvolume on the same filesystem as the directory
+    Id(u64),
+}
+
+impl Subvolume<'_> {
+    // qgroups the new subvolume is added to, its own level 0 qgroup is always
+    // created
+    pub fn create(parent_dir: &File, name: &OsStr, qgroups: &[QgroupId]) -> Result<(), Error> {
+        let mut args: btrfs_ioctl_vol_args_v2 = unsafe { mem::zeroed() };
+        let mut inherit = qgroup_inherit(qgroups);
+
+        copy_name(unsafe { &mut args.__bindgen_anon_2.name }, name)?;
+        set_qgroup_inherit(&mut args, &mut inherit);
+
+        unsafe { btrfs_subvol_create_v2(parent_dir.as_raw_fd(), &args as *const _) }
+            .map_err(Error::ioctl(Ioctl::SubvolCreate))?;
+
+        Ok(())
+    }
+
+    pub fn snapshot(
+        &self,
+        dest_dir: &File,
+        name: &OsStr,
+        readonly: bool,
+        qgroups: &[QgroupId],
+    ) -> Result<(), Error> {
+        let mut args: btrfs_ioctl_vol_args_v2 = unsafe { mem::zeroed() };
+        let mut inherit = qgroup_inherit(qgroups);
+
+        args.fd = self.as_file().as_raw_fd().into();
+
+        if readonly {
+            args.flags |= BTRFS_SUBVOL_RDONLY as u64;
+        }
+
+        copy_name(unsafe { &mut args.__bindgen_anon_2.name }, name)?;
+        set_qgroup_inherit(&mut args, &mut inherit);
+
+        unsafe { btrfs_snap_create_v2(dest_dir.as_raw_fd(), &args as *const _) }
+            .map_err(Error::ioctl(Ioctl::SnapCreate))?;
+
+        Ok(())
+    }
+
+    pub fn flags(&self) -> Result<SubvolFlags, Error> {
+        let mut flags = 0;
+
+        unsafe { btrfs_subvol_getflags(self.as_file().as_raw_fd(), &mut flags as *mut _) }
+            .map_err(Error::ioctl(Ioctl::SubvolGetflags))?;
+
+        SubvolFlags::from_bits(flags).ok_or(Error::Decode {
+            key: None,
+            what: "subvolume flags",
+        })
+    }
+
+    // making a received subvolume writable breaks incremental send, because
+    // later sends would still be based on its received uuid. like btrfs-progs
+    // this is refused unless forced, which also clears the received uuid
+    pub fn set_flags(&self, flags: SubvolFlags, force: bool) -> Result<(), Error> {
+        let current = self.flags()?;
+        let mut clear_received = false;
+
+        if current.contains(SubvolFlags::RDONLY) && !flags.contains(SubvolFlags::RDONLY) {
+            clear_received = self.info()?.received_uuid != Uuid([0; BTRFS_UUID_SIZE as usize]);
+
+            if clear_received && !force {
+                return Err(Error::ReceivedSubvol);
+            }
+        }
+
+        let bits = flags.bits();
+
+        unsafe { btrfs_subvol_setflags(self.as_file().as_raw_fd(), &bits as *const _) }
+            .map_err(Error::ioctl(Ioctl::SubvolSetflags))?;
+
+        // the kernel only lets writable subvolumes change their received uuid
+        if clear_received {
+            let mut args: btrfs_ioctl_received_subvol_args = unsafe { mem::zeroed() };
+
+            unsafe { btrfs_set_received_subvol(self.as_file().as_raw_fd(), &mut args as *mut _) }
+                .map_err(Error::ioctl(Ioctl::SetReceivedSubvol))?;
+        }
+
+        Ok(())
+    }
+
+    pub fn set_read_only(&self, read_only: bool, force: bool) -> Result<(), Error> {
+        let mut flags = self.flags()?;
+
+        flags.set(SubvolFlags::RDONLY, read_only);
+
+        self.set_flags(flags, force)
+    }
+
+    // the subvolume disappears right away but its space is only freed once the
+    // cleaner has dropped its tree, which wait blocks for up to the given timeout
+    pub fn delete(dir: &File, subvol: SubvolSpec, wait: Option<Duration>) -> Result<(), Error> {
+        let id = match subvol {
+            SubvolSpec::Name(name) => {
+                // the id has to be looked up before the subvolume is gone
+                let id = match wait {
+                    Some(_) => Some(subvol_id(dir, name)?),
+                    None => None,
+                };
+                let mut args: btrfs_ioctl_vol_args = unsafe { mem::zeroed() };
+
+                copy_name(&mut args.name, name)?;
+
+                unsafe { btrfs_snap_destroy(dir.as_raw_fd(), &args as *const _) }
+                    .map_err(Error::ioctl(Ioctl::SnapDestroy))?;
+
+                id
+            }
+            SubvolSpec::Id(id) => {
+                let mut args: btrfs_ioctl_vol_args_v2 = unsafe { mem::zeroed() };
+
+                args.flags = BTRFS_SUBVOL_SPEC_BY_ID as u64;
+                args.__bindgen_anon_2.subvolid = id;
+
+                unsafe { btrfs_snap_destroy_v2(dir.as_raw_fd(), &args as *const _) }
+                    .map_err(Error::ioctl(Ioctl::SnapDestroyV2))?;
+
+                Some(id)
+            }
+        };
+
+        match (wait, id) {
+            (Some(timeout), Some(id)) => Self::wait_cleaned(dir, id, timeout),
+            _ => Ok(()),
+        }
+    }
+
+    // fails with ETIMEDOUT if the cleaner hasn't dropped the subvolume in time
+    pub fn wait_cleaned(dir: &File, id: u64, timeout: Duration) -> Result<(), Error> {
+        let deadline = Instant::now() + timeout;
+
+        while !is_cleaned(dir, id)? {
+            let now = Instant::now();
+
+            if now >= deadline {
+                return Err(Error::Sys(Errno::ETIMEDOUT));
+            }
+
+            thread::sleep(CLEANER_POLL_INTERVAL.min(deadline - now));
+        }
+
+        Ok(())
+    }
+}
+
+impl SubvolFlags {
+    // root items keep the read-only flag in a different bit than the ioctls
+    pub(crate) fn from_root_flags(flags: u64) -> Self {
+        match flags & BTRFS_ROOT_SUBVOL_RDONLY as u64 {
+            0 => Self::empty(),
+            _ => Self::RDONLY,
+        }
+    }
+}
+
+// the default subvolume is whatever the "default" dir item in the root tree
+// points to, without one it's the top level subvolume
+pub fn default_subvolume(file: &File) -> Result<u64, Error> {
+    let search = TreeSearch::new(
+        file,
+        Tree::Root,
+        KeyRange::objectid_type(BTRFS_ROOT_TREE_DIR_OBJECTID as u64, KeyType::DirItem),
+        0..=u64::MAX,
+    );
+
+    for result in search {
+        match result? {
+            (_, Item::DirItem(DirItem::File { location, name, .. }))
+                if name.as_os_str() == "default" =>
+            {
+                return Ok(location.objectid.get())
+            }
+            _ => continue,
+        }
+    }
+
+    Ok(BTRFS_FS_TREE_OBJECTID as u64)
+}
+
+pub fn set_default_subvolume(file: &File, id: u64) -> Result<(), Error> {
+    unsafe { btrfs_default_subvol(file.as_raw_fd(), &id as *const _) }
+        .map_err(Error::ioctl(Ioctl::DefaultSubvol))?;
+
+    Ok(())
+}
+
+// like btrfs subvolume list, every subvolume reachable from the top level.
+// subvolumes that have been deleted but not cleaned up yet are left out
+pub fn list_subvolumes(file: &File, filter: SubvolFilter) -> Result<Vec<SubvolEntry>, Error> {
+    let range = KeyRange::new(
+        Key::new(BTRFS_FIRST_FREE_OBJECTID as u64, KeyType::RootItem, 0),
+        Key::new(
+            BTRFS_LAST_FREE_OBJECTID as u64,
+            KeyType::RootBackref,
+            u64::MAX,
+        ),
+    );
+
+    let mut roots = BTreeMap::<u64, Root>::new();
+    let mut backrefs = HashMap::new();
+
+    for result in TreeSearch::new(file, Tree::Root, range, 0..=u64::MAX) {
+        match result? {
+            (key, Item::Root(root)) => {
+                roots.insert(key.objectid, root);
+            }
+            (key, Item::RootBackRef(backref)) => {
+                backrefs.insert(key.objectid, (key.offset, backref));
+            }
+            _ => continue,
+        }
+    }
+
+    let mut subvol_paths = HashMap::new();
+    let mut dir_paths = HashMap::new();
+    let mut subvols = Vec::new();
+
+    for (&id, root) in &roots {
+        let nil = Uuid([0; BTRFS_UUID_SIZE as usize]);
+
+        if (filter.snapshots_only && root.parent_uuid == nil)
+            || (filter.read_only && !root.read_only)
+        {
+            continue;
+        }
+
+        let Some(path) = subvol_path(file, id, &backrefs, &mut subvol_paths, &mut dir_paths)?
+        else {
+            continue;
+        };
+
+        subvols.push(SubvolEntry {
+            id,
+            parent_id: backrefs[&id].0,
+            path,
+            generation: root.generation.get(),
+            uuid: root.uuid,
+            parent_uuid: root.parent_uuid,
+            received_uuid: root.received_uuid,
+            read_only: root.read_only,
+        });
+    }
+
+    Ok(subvols)
+}
+
+// a subvolume's path is the path of the directory it sits in inside its
+// parent subvolume, below the parent's own path
+fn subvol_path(
+    file: &File,
+    id: u64,
+    backrefs: &HashMap<u64, (u64, RootRef)>,
+    subvol_paths: &mut HashMap<u64, Option<PathBuf>>,
+    dir_paths: &mut HashMap<u64, HashMap<u64, Option<PathBuf>>>,
+) -> Result<Option<PathBuf>, Error> {
+    if id == BTRFS_FS_TREE_OBJECTID as u64 {
+        return Ok(Some(PathBuf::new()));
+    }
+
+    if let Some(path) = subvol_paths.get(&id) {
+        return Ok(path.clone());
+    }
+
+    let path = match backrefs.get(&id) {
+        Some((parent, backref)) if *parent != id => {
+            match subvol_path(file, *parent, backrefs, subvol_paths, dir_paths)? {
+                Some(parent_path) => inode_path(
+                    file,
+                    Tree::Subvol(*parent),
+                    backref.dirid.get(),
+                    dir_paths.entry(*parent).or_default(),
+                )?
+                .map(|dir| parent_path.join(dir).join(&backref.name)),
+                None => None,
+            }
+        }
+        _ => None,
+    };
+
+    subvol_paths.insert(id, path.clone());
+
+    Ok(path)
+}
+
+fn subvol_id(dir: &File, name: &OsStr) -> Result<u64, Error> {
+    let fd = fcntl::openat(
+        Some(dir.as_raw_fd()),
+        name,
+        OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?;
+    let file = unsafe { File::from_raw_fd(fd) };
+
+    match Subvolume::new(&file)? {
+        Some(subvol) => Ok(subvol.info()?.tree_id),
+        None => Err(Error::Sys(Errno::EINVAL)),
+    }
+}
+
+// a deleted subvolume keeps its root item and an orphan item in the root tree
+// until the cleaner has dropped it
+fn is_cleaned(file: &File, id: u64) -> Result<bool, Error> {
+    let mut root = TreeSearch::new(
+        file,
+        Tree::Root,
+        KeyRange::objectid_type(id, KeyType::RootItem),
+        0..=u64::MAX,
+    );
+
+    if root.next().transpose()?.is_some() {
+        return Ok(false);
+    }
+
+    let mut orphan = TreeSearch::new(
+        file,
+        Tree::Root,
+        KeyRange::key(Key::new(
+            BTRFS_ORPHAN_OBJECTID as u64,
+            KeyType::OrphanItem,
+            id,
+        )),
+        0..=u64::MAX,
+    );
+
+    Ok(orphan.next().transpose()?.is_none())
+}
+
+// the kernel takes names as nul terminated strings in a fixed size buffer
+pub(crate) fn copy_name(dst: &mut [c_char], name: &OsStr) -> Result<(), Error> {
+    let name = name.as_bytes();
+
+    if name.contains(&0) {
+        return Err(Error::Sys(Errno::EINVAL));
+    }
+
+    if name.len() > BTRFS_SUBVOL_NAME_MAX as usize || name.len() >= dst.len() {
+        return Err(Error::Sys(Errno::ENAMETOOLONG));
+    }
+
+    for (dst, &src) in dst.iter_mut().zip(name) {
+        *dst = src as c_char;
+    }
+
+    Ok(())
+}
+
+// btrfs_qgroup_inherit followed by the ids of the qgroups, as u64s so the
+// struct stays aligned
+fn qgroup_inherit(qgroups: &[QgroupId]) -> Vec<u64> {
+    if qgroups.is_empty() {
+        return Vec::new();
+    }
+
+    let mut inherit = vec![0u64; mem::size_of::<btrfs_qgroup_inherit>() / mem::size_of::<u64>()];
+
+    unsafe {
+        (*inherit.as_mut_ptr().cast::<btrfs_qgroup_inherit>()).num_qgroups = qgroups.len() as u64
+    };
+    inherit.extend(qgroups.iter().map(|qgroup| qgroup.0));
+
+    inherit
+}
+
+fn set_qgroup_inherit(args: &mut btrfs_ioctl_vol_args_v2, inherit: &mut [u64]) {
+    if inherit.is_empty() {
+        return;
+    }
+
+    args.flags |= BTRFS_SUBVOL_QGROUP_INHERIT as u64;
+    args.__bindgen_anon_1.__bindgen_anon_1.size = mem::size_of_val(inherit) as u64;
+    args.__bindgen_anon_1.__bindgen_anon_1.qgroup_inherit =
+        inherit.as_mut_ptr().cast::<btrfs_qgroup_inherit>();
+}
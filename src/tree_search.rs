@@ -1,22 +1,28 @@
 use crate::item::{
-    BlockGroup, DirIndex, DirItem, FileExtentInline, FileExtentReg, FreeSpaceHeader, Inode,
-    InodeRef, Root, RootRef,
+    Balance, BlockGroup, Chunk, Csum, DevExtent, DevItem, DevReplace, DevReplaceItem, DevStats,
+    DirIndex, DirItem, DirLog, Extent, ExtentDataRef, FileExtentInline, FileExtentReg,
+    FreeSpaceBitmap, FreeSpaceExtent, FreeSpaceHeader, FreeSpaceInfo, Inode, InodeExtref, InodeRef,
+    Orphan, QgroupInfo, QgroupLimit, QgroupRelation, QgroupStatus, Root, RootRef, SharedBlockRef,
+    SharedDataRef, TreeBlockRef, UuidItem, VerityDescItem, VerityMerkle,
 };
+use crate::item_ref::{self, ItemRef};
 
 use btrfs_sys::{
-    btrfs_block_group_item, btrfs_dir_item, btrfs_file_extent_item, btrfs_free_space_header,
-    btrfs_inode_item, btrfs_inode_ref, btrfs_ioctl_search_args_v2, btrfs_ioctl_search_header,
-    btrfs_ioctl_search_key, btrfs_root_item, btrfs_root_ref, BTRFS_BLOCK_GROUP_TREE_OBJECTID,
-    BTRFS_CHUNK_TREE_OBJECTID, BTRFS_CSUM_TREE_OBJECTID, BTRFS_DEV_TREE_OBJECTID,
-    BTRFS_EXTENT_TREE_OBJECTID, BTRFS_FILE_EXTENT_INLINE, BTRFS_FILE_EXTENT_PREALLOC,
-    BTRFS_FILE_EXTENT_REG, BTRFS_FREE_SPACE_TREE_OBJECTID, BTRFS_FS_TREE_OBJECTID,
-    BTRFS_IOCTL_MAGIC, BTRFS_QUOTA_TREE_OBJECTID, BTRFS_ROOT_TREE_DIR_OBJECTID,
-    BTRFS_ROOT_TREE_OBJECTID, BTRFS_UUID_TREE_OBJECTID,
+    btrfs_chunk, btrfs_dir_item, btrfs_extent_item, btrfs_file_extent_item,
+    btrfs_ioctl_search_args, btrfs_ioctl_search_args_v2, btrfs_ioctl_search_header,
+    btrfs_ioctl_search_key, btrfs_root_item, btrfs_root_ref, BTRFS_BALANCE_OBJECTID,
+    BTRFS_BLOCK_GROUP_TREE_OBJECTID, BTRFS_CHUNK_TREE_OBJECTID, BTRFS_CSUM_TREE_OBJECTID,
+    BTRFS_DEV_STATS_OBJECTID, BTRFS_DEV_TREE_OBJECTID, BTRFS_EXTENT_TREE_OBJECTID,
+    BTRFS_FILE_EXTENT_INLINE, BTRFS_FILE_EXTENT_PREALLOC, BTRFS_FILE_EXTENT_REG,
+    BTRFS_FREE_SPACE_TREE_OBJECTID, BTRFS_FS_TREE_OBJECTID, BTRFS_IOCTL_MAGIC,
+    BTRFS_QUOTA_TREE_OBJECTID, BTRFS_ROOT_TREE_DIR_OBJECTID, BTRFS_ROOT_TREE_OBJECTID,
+    BTRFS_UUID_TREE_OBJECTID,
 };
 
-use crate::IOCTL_BUFF_SIZE;
-use core::{convert::TryFrom, mem, slice, unreachable};
-use std::{fs::File, ops::Range, os::fd::AsRawFd};
+use crate::{fs_info, Error, FsInfo, Ioctl, QgroupId, IOCTL_BUFF_SIZE};
+use core::{convert::TryFrom, mem, slice};
+use nix::libc::c_int;
+use std::{fs::File, ops::RangeInclusive, os::fd::AsRawFd};
 
 nix::ioctl_readwrite!(
     btrfs_tree_search,
@@ -25,13 +31,22 @@ nix::ioctl_readwrite!(
     btrfs_ioctl_search_args_v2
 );
 
-#[repr(C)]
-#[allow(dead_code)]
-#[derive(Clone, Copy, Debug)]
+nix::ioctl_readwrite!(
+    btrfs_tree_search_v1,
+    BTRFS_IOCTL_MAGIC,
+    11,
+    btrfs_ioctl_search_args
+);
+
+const TREE_SEARCH_V1_BUFF_SIZE: usize =
+    mem::size_of::<btrfs_ioctl_search_args>() - mem::size_of::<btrfs_ioctl_search_key>();
+
+// btrfs_ioctl_search_args_v2 followed by its buffer, kept on the heap as u64s
+// so the struct at the front stays aligned
+#[derive(Debug)]
 pub struct TreeSearchArgs {
-    key: btrfs_ioctl_search_key,
-    size: u64,
-    buffer: [u8; IOCTL_BUFF_SIZE],
+    raw: Vec<u64>,
+    size: usize,
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -45,9 +60,38 @@ pub enum Item {
     DirItem(DirItem),
     DirIndex(DirIndex),
     Inode(Inode),
-    InodeRef(InodeRef),
+    InodeRef(Vec<InodeRef>),
+    InodeExtref(Vec<InodeExtref>),
     FreeSpaceHeader(FreeSpaceHeader),
     BlockGroup(BlockGroup),
+    Chunk(Chunk),
+    DevItem(DevItem),
+    DevExtent(DevExtent),
+    Extent(Extent),
+    Metadata(Extent),
+    TreeBlockRef(TreeBlockRef),
+    SharedBlockRef(SharedBlockRef),
+    ExtentDataRef(ExtentDataRef),
+    SharedDataRef(SharedDataRef),
+    Csum(Csum),
+    FreeSpaceInfo(FreeSpaceInfo),
+    FreeSpaceExtent(FreeSpaceExtent),
+    FreeSpaceBitmap(FreeSpaceBitmap),
+    QgroupStatus(QgroupStatus),
+    QgroupInfo(QgroupInfo),
+    QgroupLimit(QgroupLimit),
+    QgroupRelation(QgroupRelation),
+    UuidSubvol(UuidItem),
+    UuidReceivedSubvol(UuidItem),
+    Orphan(Orphan),
+    DirLogItem(DirLog),
+    DirLogIndex(DirLog),
+    Balance(Balance),
+    DevStats(DevStats),
+    DevReplace(DevReplace),
+    VerityDesc(VerityDescItem),
+    VerityMerkle(VerityMerkle),
+    Unknown { r#type: u8, data: Vec<u8> },
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -67,7 +111,7 @@ pub enum Tree {
     Subvol(u64),
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum KeyType {
     FreeSpaceHeader = 0,
     InodeItem = 1,
@@ -111,24 +155,116 @@ pub enum KeyType {
     UuidSubvol = 251,
     UuidReceivedSubvol = 252,
     StringItem = 253,
+    // key types this crate doesn't know about, the raw type is kept in Item::Unknown
+    Unknown = 255,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct Key {
     pub objectid: u64,
     pub r#type: KeyType,
     pub offset: u64,
 }
 
+// inclusive bounds on the whole (objectid, type, offset) key, the kernel
+// compares keys as a tuple so e.g. (256, InodeItem, 0)..=(257, InodeItem, 0)
+// also returns every other item of inode 256
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KeyRange {
+    pub start: Key,
+    pub end: Key,
+}
+
 #[derive(Debug)]
 pub struct TreeSearch<'a> {
     args: TreeSearchArgs,
     file: &'a File,
     bp: usize,
+    fs_info: Option<FsInfo>,
+    finished: bool,
+    r#type: Option<KeyType>,
+    ioctl: unsafe fn(c_int, *mut btrfs_ioctl_search_args_v2) -> nix::Result<c_int>,
+    // used instead of ioctl if the kernel doesn't know about it
+    fallback: Option<unsafe fn(c_int, *mut btrfs_ioctl_search_args_v2) -> nix::Result<c_int>>,
+    v1: bool,
+}
+
+// the items returned by one search, borrowed straight from the ioctl buffer.
+// items left unread are handed out again by the next batch
+#[derive(Debug)]
+pub struct Batch<'buf> {
+    buffer: &'buf [u8],
+    key: &'buf mut btrfs_ioctl_search_key,
+    bp: &'buf mut usize,
+    finished: &'buf mut bool,
+    r#type: Option<KeyType>,
+    file: &'buf File,
+    fs_info: &'buf mut Option<FsInfo>,
+}
+
+impl KeyType {
+    pub const MIN: Self = Self::FreeSpaceHeader;
+    pub const MAX: Self = Self::Unknown;
+}
+
+impl Key {
+    pub const MIN: Self = Self {
+        objectid: 0,
+        r#type: KeyType::MIN,
+        offset: 0,
+    };
+    pub const MAX: Self = Self {
+        objectid: u64::MAX,
+        r#type: KeyType::MAX,
+        offset: u64::MAX,
+    };
+
+    pub fn new(objectid: u64, r#type: KeyType, offset: u64) -> Self {
+        Self {
+            objectid,
+            r#type,
+            offset,
+        }
+    }
+}
+
+impl KeyRange {
+    pub fn new(start: Key, end: Key) -> Self {
+        Self { start, end }
+    }
+
+    pub fn all() -> Self {
+        Self::new(Key::MIN, Key::MAX)
+    }
+
+    // every item of one object, e.g. all items of an inode
+    pub fn objectid(objectid: u64) -> Self {
+        Self::new(
+            Key::new(objectid, KeyType::MIN, 0),
+            Key::new(objectid, KeyType::MAX, u64::MAX),
+        )
+    }
+
+    // every item of one type belonging to one object, e.g. the file extents
+    // of an inode
+    pub fn objectid_type(objectid: u64, r#type: KeyType) -> Self {
+        Self::new(
+            Key::new(objectid, r#type, 0),
+            Key::new(objectid, r#type, u64::MAX),
+        )
+    }
+
+    pub fn key(key: Key) -> Self {
+        Self::new(key, key)
+    }
+
+    pub fn contains(&self, key: &Key) -> bool {
+        self.start <= *key && *key <= self.end
+    }
 }
 
 impl TryFrom<u32> for KeyType {
-    type Error = ();
+    type Error = Error;
 
     fn try_from(value: u32) -> Result<Self, Self::Error> {
         Ok(match value {
@@ -174,7 +310,7 @@ impl TryFrom<u32> for KeyType {
             251 => Self::UuidSubvol,
             252 => Self::UuidReceivedSubvol,
             253 => Self::StringItem,
-            _ => return Err(()),
+            _ => return Err(Error::UnknownKeyType(value)),
         })
     }
 }
@@ -202,22 +338,21 @@ impl Tree {
 impl TreeSearchArgs {
     pub fn new(
         tree_id: u64,
-        objectids: Range<u64>,
-        offsets: Range<u64>,
-        transids: Range<u64>,
-        types: Range<u32>,
+        range: KeyRange,
+        transids: RangeInclusive<u64>,
         items: u32,
+        size: usize,
     ) -> Self {
         let key = btrfs_ioctl_search_key {
             tree_id,
-            min_objectid: objectids.start,
-            max_objectid: objectids.end,
-            min_offset: offsets.start,
-            max_offset: offsets.end,
-            min_transid: transids.start,
-            max_transid: transids.end,
-            min_type: types.start,
-            max_type: types.end,
+            min_objectid: range.start.objectid,
+            max_objectid: range.end.objectid,
+            min_offset: range.start.offset,
+            max_offset: range.end.offset,
+            min_transid: *transids.start(),
+            max_transid: *transids.end(),
+            min_type: range.start.r#type as u32,
+            max_type: range.end.r#type as u32,
             nr_items: items,
             unused: Default::default(),
             unused1: Default::default(),
@@ -226,257 +361,940 @@ impl TreeSearchArgs {
             unused4: Default::default(),
         };
 
-        Self {
-            key,
-            size: IOCTL_BUFF_SIZE.try_into().unwrap(),
-            buffer: [0; IOCTL_BUFF_SIZE],
+        let mut args = Self {
+            raw: Vec::new(),
+            size: 0,
+        };
+
+        args.resize(size);
+        args.key_mut().clone_from(&key);
+
+        args
+    }
+
+    // the key is kept, whatever is in the buffer is not
+    fn resize(&mut self, size: usize) {
+        self.raw.resize(
+            (mem::size_of::<btrfs_ioctl_search_args_v2>() + size).div_ceil(mem::size_of::<u64>()),
+            0,
+        );
+        self.size = size;
+    }
+
+    fn as_mut_ptr(&mut self) -> *mut btrfs_ioctl_search_args_v2 {
+        self.raw.as_mut_ptr().cast()
+    }
+
+    fn key(&self) -> &btrfs_ioctl_search_key {
+        unsafe { &(*self.raw.as_ptr().cast::<btrfs_ioctl_search_args_v2>()).key }
+    }
+
+    fn key_mut(&mut self) -> &mut btrfs_ioctl_search_key {
+        unsafe { &mut (*self.as_mut_ptr()).key }
+    }
+
+    // the key and the buffer borrowed separately, so items can be read out of
+    // the buffer while the key is advanced past them
+    fn split(&mut self) -> (&mut btrfs_ioctl_search_key, &[u8]) {
+        let size = self.size;
+        let args = self.as_mut_ptr();
+
+        unsafe {
+            (
+                &mut (*args).key,
+                slice::from_raw_parts(
+                    args.cast::<u8>()
+                        .add(mem::size_of::<btrfs_ioctl_search_args_v2>()),
+                    size,
+                ),
+            )
         }
     }
 }
 
 impl<'a> TreeSearch<'a> {
     pub fn search_all(file: &'a File, tree: Tree) -> Self {
-        Self::new(
+        Self::new(file, tree, KeyRange::all(), 0..=u64::MAX)
+    }
+
+    pub fn new(file: &'a File, tree: Tree, range: KeyRange, transids: RangeInclusive<u64>) -> Self {
+        let args = TreeSearchArgs::new(tree.into_u64(), range, transids, 0, IOCTL_BUFF_SIZE);
+
+        Self {
+            args,
             file,
-            tree,
-            0..u64::MAX,
-            0..u64::MAX,
-            0..u64::MAX,
-            0..u32::MAX,
-        )
+            bp: 0,
+            fs_info: None,
+            finished: false,
+            r#type: None,
+            ioctl: btrfs_tree_search,
+            fallback: Some(tree_search_v1),
+            v1: false,
+        }
     }
 
-    pub fn new(
-        file: &'a File,
-        tree: Tree,
-        objectids: Range<u64>,
-        offsets: Range<u64>,
-        transids: Range<u64>,
-        types: Range<u32>,
-    ) -> Self {
-        let args = TreeSearchArgs::new(tree.into_u64(), objectids, offsets, transids, types, 0);
+    // items of one type are spread over the whole tree and the kernel can only
+    // bound the compound key, so everything in between is skipped here
+    pub fn of_type(file: &'a File, tree: Tree, r#type: KeyType) -> Self {
+        let range = KeyRange::new(Key::new(0, r#type, 0), Key::new(u64::MAX, r#type, u64::MAX));
 
-        Self { args, file, bp: 0 }
+        Self {
+            r#type: Some(r#type),
+            ..Self::new(file, tree, range, 0..=u64::MAX)
+        }
+    }
+
+    // the buffer still grows on its own when a single item doesn't fit
+    pub fn with_buffer_size(mut self, size: usize) -> Self {
+        self.args.resize(size);
+
+        // whatever was left of the last batch is gone, the min key already
+        // points past the items that were handed out so they are searched again
+        self.args.key_mut().nr_items = 0;
+        self.bp = 0;
+
+        self
     }
 }
 
-impl Iterator for TreeSearch<'_> {
-    type Item = Result<(Key, Item), nix::Error>;
+impl TreeSearch<'_> {
+    // runs the next search once the items of the last one have been used up
+    pub fn next_batch(&mut self) -> Option<Result<Batch<'_>, Error>> {
+        if self.args.key().nr_items == 0 {
+            if self.finished {
+                return None;
+            }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.args.key.nr_items == 0 {
             self.bp = 0;
-            self.args.key.nr_items = u32::MAX;
 
-            match unsafe {
-                btrfs_tree_search(
-                    self.file.as_raw_fd(),
-                    (&mut self.args as *mut TreeSearchArgs).cast::<btrfs_ioctl_search_args_v2>(),
-                )
-            } {
-                Ok(_) => (),
-                Err(e) => return Some(Err(e)),
+            loop {
+                self.args.key_mut().nr_items = u32::MAX;
+                unsafe { (*self.args.as_mut_ptr()).buf_size = self.args.size as u64 };
+
+                match unsafe { (self.ioctl)(self.file.as_raw_fd(), self.args.as_mut_ptr()) } {
+                    Ok(_) => break,
+                    // the kernel couldn't fit even a single item into the buffer
+                    // and reports the size that item needs in buf_size
+                    Err(nix::Error::EOVERFLOW) => {
+                        let needed = unsafe { (*self.args.as_mut_ptr()).buf_size } as usize;
+
+                        if needed > self.args.size {
+                            self.args.resize(needed.next_power_of_two());
+                            continue;
+                        }
+
+                        self.args.key_mut().nr_items = 0;
+                        self.finished = true;
+
+                        return Some(Err(Error::BufferTooSmall {
+                            key: None,
+                            size: self.args.size,
+                        }));
+                    }
+                    // kernels older than 3.16 only have the v1 ioctl
+                    Err(nix::Error::ENOTTY | nix::Error::EINVAL) if self.fallback.is_some() => {
+                        self.ioctl = self.fallback.take().unwrap();
+                        self.v1 = true;
+                        self.args
+                            .resize(self.args.size.max(TREE_SEARCH_V1_BUFF_SIZE));
+                    }
+                    Err(e) => {
+                        self.args.key_mut().nr_items = 0;
+                        self.finished = true;
+
+                        let ioctl = match self.v1 {
+                            true => Ioctl::TreeSearchV1,
+                            false => Ioctl::TreeSearch,
+                        };
+
+                        return Some(Err(Error::ioctl(ioctl)(e)));
+                    }
+                }
+            }
+
+            // v1 can't report EOVERFLOW, instead the item that doesn't fit comes
+            // back alone with its length zeroed. there is no bigger buffer to
+            // retry with, so the item is reported and the search goes on after it
+            if self.v1 && self.args.key().nr_items == 1 {
+                let (_, buffer) = self.args.split();
+
+                if let Ok(header) = read_item::<btrfs_ioctl_search_header>(buffer) {
+                    if header.len == 0 && !empty_item(header.type_) {
+                        let search_key = self.args.key_mut();
+
+                        search_key.nr_items = 0;
+
+                        match next_key(header.objectid, header.type_, header.offset) {
+                            Some((objectid, r#type, offset)) => {
+                                search_key.min_objectid = objectid;
+                                search_key.min_type = r#type;
+                                search_key.min_offset = offset;
+                            }
+                            None => self.finished = true,
+                        }
+
+                        return Some(Err(Error::BufferTooSmall {
+                            key: Some(Key {
+                                objectid: header.objectid,
+                                r#type: KeyType::try_from(header.type_).unwrap_or(KeyType::Unknown),
+                                offset: header.offset,
+                            }),
+                            size: TREE_SEARCH_V1_BUFF_SIZE,
+                        }));
+                    }
+                }
             }
 
             // if the ioctl returns 0, we are finished
-            if self.args.key.nr_items == 0 {
+            if self.args.key().nr_items == 0 {
+                self.finished = true;
+
                 return None;
             }
         }
 
-        let header = unsafe {
-            self.args.buffer[self.bp..]
-                .as_ptr()
-                .cast::<btrfs_ioctl_search_header>()
-                .read_unaligned()
-        };
+        let (key, buffer) = self.args.split();
 
-        let key = Key {
-            objectid: header.objectid,
-            r#type: KeyType::try_from(header.type_).unwrap(),
-            offset: header.offset,
-        };
+        Some(Ok(Batch {
+            buffer,
+            key,
+            bp: &mut self.bp,
+            finished: &mut self.finished,
+            r#type: self.r#type,
+            file: self.file,
+            fs_info: &mut self.fs_info,
+        }))
+    }
+}
 
-        let item = match key.r#type {
-            KeyType::RootItem => {
-                let root = unsafe {
-                    self.args.buffer[self.bp + mem::size_of::<btrfs_ioctl_search_header>()..]
-                        .as_ptr()
-                        .cast::<btrfs_root_item>()
-                        .read_unaligned()
-                };
+impl Batch<'_> {
+    // some items can only be decoded with filesystem wide parameters like the
+    // csum size or sectorsize, so look them up the first time they're needed
+    fn fs_info(&mut self) -> Result<FsInfo, Error> {
+        match *self.fs_info {
+            Some(info) => Ok(info),
+            None => Ok(*self.fs_info.insert(fs_info(self.file)?)),
+        }
+    }
 
-                Item::Root(Root::from_c_struct(root))
-            }
-            KeyType::RootRef | KeyType::RootBackref => {
-                let root_ref = unsafe {
-                    self.args.buffer[self.bp + mem::size_of::<btrfs_ioctl_search_header>()..]
-                        .as_ptr()
-                        .cast::<btrfs_root_ref>()
-                        .read_unaligned()
-                };
-
-                let name_offset = self.bp
-                    + mem::size_of::<btrfs_ioctl_search_header>()
-                    + mem::size_of::<btrfs_root_ref>();
-
-                let slice = unsafe {
-                    slice::from_raw_parts(
-                        self.args.buffer[name_offset..].as_ptr(),
-                        root_ref.name_len as usize,
-                    )
-                };
-
-                match key.r#type {
-                    KeyType::RootRef => Item::RootRef(RootRef::from_c_struct(root_ref, slice)),
-                    KeyType::RootBackref => {
-                        Item::RootBackRef(RootRef::from_c_struct(root_ref, slice))
-                    }
-                    _ => unreachable!(),
+    // a buffer we can't parse leaves no way to know where the search should
+    // resume, so give up on it
+    fn truncated(&mut self, key: Option<Key>, needed: usize, available: usize) -> Error {
+        self.key.nr_items = 0;
+        *self.finished = true;
+
+        Error::Truncated {
+            key,
+            needed,
+            available,
+        }
+    }
+}
+
+impl<'buf> Iterator for Batch<'buf> {
+    type Item = Result<(Key, ItemRef<'buf>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.key.nr_items > 0 {
+            let buffer = self.buffer;
+
+            let header = match read_item::<btrfs_ioctl_search_header>(
+                buffer.get(*self.bp..).unwrap_or_default(),
+            ) {
+                Ok(header) => header,
+                Err(()) => {
+                    let available = buffer.len().saturating_sub(*self.bp);
+
+                    return Some(Err(self.truncated(
+                        None,
+                        mem::size_of::<btrfs_ioctl_search_header>(),
+                        available,
+                    )));
                 }
+            };
+
+            let key = Key {
+                objectid: header.objectid,
+                r#type: KeyType::try_from(header.type_).unwrap_or(KeyType::Unknown),
+                offset: header.offset,
+            };
+
+            let start = *self.bp + mem::size_of::<btrfs_ioctl_search_header>();
+
+            let data = match buffer.get(start..start + header.len as usize) {
+                Some(data) => data,
+                None => {
+                    let available = buffer.len().saturating_sub(start);
+
+                    return Some(Err(self.truncated(
+                        Some(key),
+                        header.len as usize,
+                        available,
+                    )));
+                }
+            };
+
+            *self.bp = start + header.len as usize;
+            self.key.nr_items -= 1;
+
+            // the next search has to start right after this key, keys are
+            // compared as (objectid, type, offset) so the increment carries
+            // from the offset into the type and then into the objectid
+            match next_key(header.objectid, header.type_, header.offset) {
+                Some((objectid, r#type, offset)) => {
+                    self.key.min_objectid = objectid;
+                    self.key.min_type = r#type;
+                    self.key.min_offset = offset;
+                }
+                None => *self.finished = true,
             }
-            KeyType::InodeItem => {
-                let inode = unsafe {
-                    self.args.buffer[self.bp + mem::size_of::<btrfs_ioctl_search_header>()..]
-                        .as_ptr()
-                        .cast::<btrfs_inode_item>()
-                        .read_unaligned()
-                };
-
-                Item::Inode(Inode::from_c_struct(inode))
-            }
-            KeyType::ChunkItem => todo!("chunk item"),
-            KeyType::DevItem => todo!("dev item"),
-            KeyType::DevExtent => todo!("dev extent item"),
-            KeyType::PersistentItem => todo!("persistence item"),
-            KeyType::DevReplace => todo!("dev replace item"),
-            KeyType::BlockGroupItem => {
-                let block_group = unsafe {
-                    self.args.buffer[self.bp + mem::size_of::<btrfs_ioctl_search_header>()..]
-                        .as_ptr()
-                        .cast::<btrfs_block_group_item>()
-                        .read_unaligned()
-                };
-
-                Item::BlockGroup(BlockGroup::from_c_struct(block_group).unwrap())
+
+            if self
+                .r#type
+                .is_some_and(|r#type| r#type as u32 != header.type_)
+            {
+                continue;
             }
-            KeyType::ExtentData => {
-                let file_extent = unsafe {
-                    self.args.buffer[self.bp + mem::size_of::<btrfs_ioctl_search_header>()..]
-                        .as_ptr()
-                        .cast::<btrfs_file_extent_item>()
-                        .read_unaligned()
-                };
-
-                match file_extent.type_ as u32 {
-                    BTRFS_FILE_EXTENT_REG | BTRFS_FILE_EXTENT_PREALLOC => {
-                        Item::FileExtentReg(FileExtentReg::from_c_struct(file_extent))
+
+            let fs_info = match key.r#type {
+                KeyType::CsumItem | KeyType::ExtentCsum | KeyType::FreeSpaceBitmap => {
+                    match self.fs_info() {
+                        Ok(info) => Some(info),
+                        Err(e) => return Some(Err(e)),
                     }
-                    BTRFS_FILE_EXTENT_INLINE => {
-                        let data = unsafe {
-                            let offset = self.bp
-                                + mem::size_of::<btrfs_ioctl_search_header>()
-                                + mem::size_of::<u64>() * 2
-                                + 1;
-
-                            slice::from_raw_parts(
-                                self.args.buffer[offset..].as_ptr(),
-                                header.len.try_into().unwrap(),
-                            )
-                        };
+                }
+                _ => None,
+            };
+
+            // anything we can't decode is handed back raw instead of failing
+            // the whole search
+            let item = decode_item(&key, data, fs_info).unwrap_or(ItemRef::Unknown {
+                r#type: header.type_ as u8,
+                data,
+            });
+
+            return Some(Ok((key, item)));
+        }
+
+        None
+    }
+}
+
+impl Iterator for TreeSearch<'_> {
+    type Item = Result<(Key, Item), Error>;
 
-                        Item::FileExtentInline(FileExtentInline::from_c_struct_and_data(
-                            file_extent,
-                            data,
-                        ))
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.next_batch()? {
+                Ok(mut batch) => {
+                    if let Some(result) = batch.next() {
+                        return Some(result.map(|(key, item)| (key, item.to_owned())));
                     }
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+    }
+}
+
+// the v1 ioctl has a fixed size buffer right after the key instead of a
+// buf_size field, so the search is copied into that layout and back
+unsafe fn tree_search_v1(fd: c_int, args: *mut btrfs_ioctl_search_args_v2) -> nix::Result<c_int> {
+    let mut args_v1: btrfs_ioctl_search_args = unsafe { mem::zeroed() };
 
-                    _ => unreachable!(),
+    args_v1.key = unsafe { (*args).key };
+
+    let ret = unsafe { btrfs_tree_search_v1(fd, &mut args_v1 as *mut _) }?;
+    let size = unsafe { (*args).buf_size as usize }.min(TREE_SEARCH_V1_BUFF_SIZE);
+
+    unsafe {
+        (*args).key = args_v1.key;
+        args.cast::<u8>()
+            .add(mem::size_of::<btrfs_ioctl_search_args_v2>())
+            .copy_from_nonoverlapping(args_v1.buf.as_ptr().cast::<u8>(), size);
+    }
+
+    Ok(ret)
+}
+
+// items that legitimately have no data, anything else with a zero length
+// didn't fit into the buffer
+fn empty_item(r#type: u32) -> bool {
+    matches!(
+        KeyType::try_from(r#type),
+        Ok(KeyType::OrphanItem
+            | KeyType::FreeSpaceExtent
+            | KeyType::QgroupRelation
+            | KeyType::TreeBlockRef
+            | KeyType::SharedBlockRef
+            | KeyType::Unknown)
+            | Err(_)
+    )
+}
+
+fn next_key(objectid: u64, r#type: u32, offset: u64) -> Option<(u64, u32, u64)> {
+    if offset < u64::MAX {
+        Some((objectid, r#type, offset + 1))
+    } else if r#type < u32::from(u8::MAX) {
+        Some((objectid, r#type + 1, 0))
+    } else if objectid < u64::MAX {
+        Some((objectid + 1, 0, 0))
+    } else {
+        None
+    }
+}
+
+fn read_item<T: Copy>(data: &[u8]) -> Result<T, ()> {
+    if data.len() < mem::size_of::<T>() {
+        return Err(());
+    }
+
+    Ok(unsafe { data.as_ptr().cast::<T>().read_unaligned() })
+}
+
+// some items grew over time and older filesystems still store the shorter
+// versions, the fields they don't have are zeroed like the kernel does
+fn read_item_padded<T: Copy>(data: &[u8]) -> T {
+    let mut item = mem::MaybeUninit::<T>::zeroed();
+    let len = data.len().min(mem::size_of::<T>());
+
+    unsafe {
+        item.as_mut_ptr()
+            .cast::<u8>()
+            .copy_from_nonoverlapping(data.as_ptr(), len);
+
+        item.assume_init()
+    }
+}
+
+fn decode_item<'buf>(
+    key: &Key,
+    data: &'buf [u8],
+    fs_info: Option<FsInfo>,
+) -> Result<ItemRef<'buf>, ()> {
+    Ok(match key.r#type {
+        KeyType::RootItem => ItemRef::Root(Root::from_c_struct(
+            read_item_padded::<btrfs_root_item>(data),
+        )),
+        KeyType::RootRef | KeyType::RootBackref => {
+            let root_ref = read_item::<btrfs_root_ref>(data)?;
+
+            let name = data
+                .get(
+                    mem::size_of::<btrfs_root_ref>()
+                        ..mem::size_of::<btrfs_root_ref>() + root_ref.name_len as usize,
+                )
+                .ok_or(())?;
+
+            match key.r#type {
+                KeyType::RootRef => {
+                    ItemRef::RootRef(item_ref::RootRef::from_c_struct(root_ref, name))
                 }
+                _ => ItemRef::RootBackRef(item_ref::RootRef::from_c_struct(root_ref, name)),
             }
-            KeyType::ExtentItem => todo!("extent item"),
-            KeyType::MetadataItem => todo!("metadata item"),
-            KeyType::CsumItem => todo!("checksum item"),
-            KeyType::FreeSpaceInfo => todo!("free space info item"),
-            KeyType::FreeSpaceExtent => todo!("free space extent item"),
-            KeyType::FreeSpaceBitmap => todo!("free space bitmap item"),
-            KeyType::FreeSpaceHeader => {
-                let free_space_header = unsafe {
-                    self.args.buffer[self.bp + mem::size_of::<btrfs_ioctl_search_header>()..]
-                        .as_ptr()
-                        .cast::<btrfs_free_space_header>()
-                        .read_unaligned()
-                };
-
-                Item::FreeSpaceHeader(FreeSpaceHeader::from_c_struct(free_space_header))
+        }
+        KeyType::InodeItem => ItemRef::Inode(Inode::from_c_struct(read_item(data)?)),
+        KeyType::ChunkItem => {
+            let chunk = read_item::<btrfs_chunk>(data)?;
+
+            // the first stripe is embedded in btrfs_chunk, the rest follow it
+            let stripes = &data[mem::offset_of!(btrfs_chunk, stripe)..];
+
+            ItemRef::Chunk(item_ref::Chunk::from_c_struct(chunk, stripes)?)
+        }
+        KeyType::DevItem => ItemRef::DevItem(DevItem::from_c_struct(read_item(data)?)),
+        KeyType::DevExtent => ItemRef::DevExtent(DevExtent::from_c_struct(read_item(data)?)),
+        // device stats are the only user of the persistent item key
+        KeyType::PersistentItem if key.objectid == BTRFS_DEV_STATS_OBJECTID as u64 => {
+            ItemRef::DevStats(DevStats::from_c_struct(read_item(data)?, key.offset))
+        }
+        KeyType::DevReplace => ItemRef::DevReplace(DevReplace::from_c_struct(read_item::<
+            DevReplaceItem,
+        >(data)?)?),
+        KeyType::BlockGroupItem => {
+            ItemRef::BlockGroup(BlockGroup::from_c_struct(read_item(data)?)?)
+        }
+        KeyType::ExtentData => {
+            // inline extents end where disk_bytenr would start
+            let inline_offset = mem::offset_of!(btrfs_file_extent_item, disk_bytenr);
+            let file_extent = read_item_padded::<btrfs_file_extent_item>(data);
+
+            match file_extent.type_ as u32 {
+                BTRFS_FILE_EXTENT_REG | BTRFS_FILE_EXTENT_PREALLOC => {
+                    read_item::<btrfs_file_extent_item>(data)?;
+
+                    ItemRef::FileExtentReg(FileExtentReg::from_c_struct(file_extent)?)
+                }
+                BTRFS_FILE_EXTENT_INLINE => {
+                    ItemRef::FileExtentInline(item_ref::FileExtentInline::from_c_struct_and_data(
+                        file_extent,
+                        data.get(inline_offset..).ok_or(())?,
+                    )?)
+                }
+                _ => return Err(()),
             }
-            KeyType::DirItem | KeyType::DirIndex | KeyType::XattrItem => {
-                let dir = unsafe {
-                    self.args.buffer[self.bp + mem::size_of::<btrfs_ioctl_search_header>()..]
-                        .as_ptr()
-                        .cast::<btrfs_dir_item>()
-                        .read_unaligned()
-                };
-
-                let name_offset = self.bp
-                    + mem::size_of::<btrfs_ioctl_search_header>()
-                    + mem::size_of::<btrfs_dir_item>();
-
-                let slice = unsafe {
-                    slice::from_raw_parts(
-                        self.args.buffer[name_offset..].as_ptr(),
-                        dir.name_len as usize + dir.data_len as usize,
-                    )
-                };
-
-                match key.r#type {
-                    KeyType::DirItem | KeyType::XattrItem => {
-                        Item::DirItem(DirItem::from_c_struct(dir, slice))
-                    }
-                    KeyType::DirIndex => Item::DirIndex(DirIndex::from_c_struct(dir, slice)),
-                    _ => unreachable!(),
+        }
+        KeyType::ExtentItem | KeyType::MetadataItem => {
+            let extent = read_item::<btrfs_extent_item>(data)?;
+            let refs = &data[mem::size_of::<btrfs_extent_item>()..];
+
+            match key.r#type {
+                KeyType::ExtentItem => {
+                    ItemRef::Extent(item_ref::Extent::from_c_struct(extent, refs, true)?)
                 }
+                _ => ItemRef::Metadata(item_ref::Extent::from_c_struct(extent, refs, false)?),
             }
-            KeyType::InodeRef => {
-                let inode_ref = unsafe {
-                    self.args.buffer[self.bp + mem::size_of::<btrfs_ioctl_search_header>()..]
-                        .as_ptr()
-                        .cast::<btrfs_inode_ref>()
-                        .read_unaligned()
-                };
-
-                let name_offset = self.bp
-                    + mem::size_of::<btrfs_ioctl_search_header>()
-                    + mem::size_of::<btrfs_inode_ref>();
-
-                let slice = unsafe {
-                    slice::from_raw_parts(
-                        self.args.buffer[name_offset..].as_ptr(),
-                        inode_ref.name_len as usize,
-                    )
-                };
-
-                Item::InodeRef(InodeRef::from_c_struct(inode_ref, slice))
+        }
+        KeyType::TreeBlockRef => ItemRef::TreeBlockRef(TreeBlockRef::from_key_offset(key.offset)),
+        KeyType::SharedBlockRef => {
+            ItemRef::SharedBlockRef(SharedBlockRef::from_key_offset(key.offset))
+        }
+        KeyType::ExtentDataRef => {
+            ItemRef::ExtentDataRef(ExtentDataRef::from_c_struct(read_item(data)?, key.offset))
+        }
+        KeyType::SharedDataRef => {
+            ItemRef::SharedDataRef(SharedDataRef::from_c_struct(read_item(data)?, key.offset))
+        }
+        KeyType::CsumItem | KeyType::ExtentCsum => ItemRef::Csum(item_ref::Csum::from_data(
+            key.offset,
+            data,
+            fs_info.ok_or(())?.csum_size,
+        )?),
+        KeyType::FreeSpaceInfo => {
+            ItemRef::FreeSpaceInfo(FreeSpaceInfo::from_c_struct(read_item(data)?)?)
+        }
+        KeyType::FreeSpaceExtent => ItemRef::FreeSpaceExtent(FreeSpaceExtent {
+            start: key.objectid,
+            length: key.offset,
+        }),
+        KeyType::FreeSpaceBitmap => ItemRef::FreeSpaceBitmap(item_ref::FreeSpaceBitmap::from_data(
+            key.objectid,
+            key.offset,
+            fs_info.ok_or(())?.sectorsize,
+            data,
+        )?),
+        KeyType::FreeSpaceHeader => {
+            ItemRef::FreeSpaceHeader(FreeSpaceHeader::from_c_struct(read_item(data)?))
+        }
+        KeyType::DirItem | KeyType::DirIndex | KeyType::XattrItem => {
+            let dir = read_item::<btrfs_dir_item>(data)?;
+
+            let slice = data
+                .get(
+                    mem::size_of::<btrfs_dir_item>()
+                        ..mem::size_of::<btrfs_dir_item>()
+                            + dir.name_len as usize
+                            + dir.data_len as usize,
+                )
+                .ok_or(())?;
+
+            match key.r#type {
+                KeyType::DirIndex => {
+                    ItemRef::DirIndex(item_ref::DirIndex::from_c_struct(dir, slice)?)
+                }
+                _ => ItemRef::DirItem(item_ref::DirItem::from_c_struct(dir, slice)?),
             }
-            KeyType::InodeExtref => todo!("inode extref item"),
-            KeyType::QgroupStatus => todo!("qgroup status item"),
-            KeyType::QgroupInfo => todo!("qgroup info item"),
-            KeyType::QgroupLimit => todo!("qgroup limit item"),
-            KeyType::QgroupRelation => todo!("qgroup relation item"),
-            KeyType::OrphanItem => todo!("orphan item"),
-            KeyType::DirLogItem => todo!("dir log item"),
-            KeyType::TemporaryItem => todo!("balance item"),
-            KeyType::UuidSubvol | KeyType::UuidReceivedSubvol => todo!("uuid item"),
-            _ => todo!("{:?}", key.r#type),
+        }
+        KeyType::InodeRef => ItemRef::InodeRef(item_ref::InodeRefs::from_data(data)?),
+        KeyType::InodeExtref => ItemRef::InodeExtref(item_ref::InodeExtrefs::from_data(data)?),
+        KeyType::QgroupStatus => {
+            ItemRef::QgroupStatus(QgroupStatus::from_c_struct(read_item(data)?)?)
+        }
+        KeyType::QgroupInfo => {
+            ItemRef::QgroupInfo(QgroupInfo::from_c_struct(read_item(data)?, key.offset))
+        }
+        KeyType::QgroupLimit => {
+            ItemRef::QgroupLimit(QgroupLimit::from_c_struct(read_item(data)?, key.offset)?)
+        }
+        KeyType::QgroupRelation => ItemRef::QgroupRelation(QgroupRelation {
+            src: QgroupId(key.objectid),
+            dst: QgroupId(key.offset),
+        }),
+        KeyType::OrphanItem => ItemRef::Orphan(Orphan {
+            objectid: key.offset,
+        }),
+        KeyType::DirLogItem => ItemRef::DirLogItem(DirLog::from_c_struct(read_item(data)?)),
+        KeyType::DirLogIndex => ItemRef::DirLogIndex(DirLog::from_c_struct(read_item(data)?)),
+        // the balance item is the only user of the temporary item key
+        KeyType::TemporaryItem if key.objectid == BTRFS_BALANCE_OBJECTID as u64 => {
+            ItemRef::Balance(Balance::from_c_struct(read_item(data)?)?)
+        }
+        KeyType::UuidSubvol => ItemRef::UuidSubvol(item_ref::UuidItem::from_data(
+            key.objectid,
+            key.offset,
+            data,
+        )?),
+        KeyType::UuidReceivedSubvol => ItemRef::UuidReceivedSubvol(item_ref::UuidItem::from_data(
+            key.objectid,
+            key.offset,
+            data,
+        )?),
+        KeyType::VerityDescItem => {
+            ItemRef::VerityDesc(item_ref::VerityDescItem::from_data(key.offset, data)?)
+        }
+        KeyType::VerityMerkleItem => ItemRef::VerityMerkle(item_ref::VerityMerkle {
+            offset: key.offset,
+            data,
+        }),
+        _ => return Err(()),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // keys in the order the kernel stores them
+    const KEYS: &[(u64, u32, u64)] = &[
+        (256, KeyType::InodeItem as u32, 0),
+        (256, KeyType::ExtentData as u32, 0),
+        (256, KeyType::ExtentData as u32, 4096),
+        (256, KeyType::ExtentData as u32, 8192),
+        (256, KeyType::ExtentData as u32, 12288),
+        (257, KeyType::InodeItem as u32, 0),
+        (257, KeyType::InodeRef as u32, 256),
+        (257, KeyType::ExtentData as u32, 0),
+        (258, KeyType::OrphanItem as u32, u64::MAX),
+        (258, KeyType::DirLogItem as u32, 0),
+        (259, 255, u64::MAX),
+        (260, KeyType::InodeItem as u32, 0),
+        (u64::MAX, 255, u64::MAX),
+    ];
+
+    // only ever hands out two items per call so every search has to resume
+    unsafe fn fake_tree_search(
+        _: c_int,
+        args: *mut btrfs_ioctl_search_args_v2,
+    ) -> nix::Result<c_int> {
+        let key = unsafe { &mut (*args).key };
+        let buffer = unsafe {
+            args.cast::<u8>()
+                .add(mem::size_of::<btrfs_ioctl_search_args_v2>())
+        };
+        let min = (key.min_objectid, key.min_type, key.min_offset);
+        let max = (key.max_objectid, key.max_type, key.max_offset);
+
+        let mut bp = 0;
+        let mut nr_items = 0;
+
+        for &(objectid, r#type, offset) in
+            KEYS.iter().filter(|&&key| key >= min && key <= max).take(2)
+        {
+            let header = btrfs_ioctl_search_header {
+                transid: 0,
+                objectid,
+                offset,
+                type_: r#type,
+                len: 0,
+            };
+
+            unsafe {
+                buffer
+                    .add(bp)
+                    .cast::<btrfs_ioctl_search_header>()
+                    .write_unaligned(header)
+            };
+
+            bp += mem::size_of::<btrfs_ioctl_search_header>();
+            nr_items += 1;
+        }
+
+        key.nr_items = nr_items;
+
+        Ok(0)
+    }
+
+    const LARGE_ITEM: usize = 100_000;
+
+    // a single item that is bigger than the default buffer
+    unsafe fn fake_large_item(
+        _: c_int,
+        args: *mut btrfs_ioctl_search_args_v2,
+    ) -> nix::Result<c_int> {
+        let args = unsafe { &mut *args };
+        let needed = mem::size_of::<btrfs_ioctl_search_header>() + LARGE_ITEM;
+
+        if (
+            args.key.min_objectid,
+            args.key.min_type,
+            args.key.min_offset,
+        ) > (256, KeyType::XattrItem as u32, 0)
+        {
+            args.key.nr_items = 0;
+
+            return Ok(0);
+        }
+
+        if (args.buf_size as usize) < needed {
+            args.key.nr_items = 0;
+            args.buf_size = needed as u64;
+
+            return Err(nix::Error::EOVERFLOW);
+        }
+
+        let header = btrfs_ioctl_search_header {
+            transid: 0,
+            objectid: 256,
+            offset: 0,
+            type_: KeyType::XattrItem as u32,
+            len: LARGE_ITEM as u32,
+        };
+
+        unsafe {
+            args.buf
+                .as_mut_ptr()
+                .cast::<btrfs_ioctl_search_header>()
+                .write_unaligned(header)
+        };
+
+        args.key.nr_items = 1;
+
+        Ok(0)
+    }
+
+    fn search(mut search: TreeSearch<'_>) -> Vec<(u64, u32, u64)> {
+        search.ioctl = fake_tree_search;
+
+        search
+            .map(|result| {
+                let (key, item) = result.unwrap();
+
+                match item {
+                    Item::Unknown { r#type, .. } => (key.objectid, u32::from(r#type), key.offset),
+                    _ => (key.objectid, key.r#type as u32, key.offset),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pagination_returns_every_key() {
+        let file = File::open("/dev/null").unwrap();
+
+        assert_eq!(search(TreeSearch::search_all(&file, Tree::Fs)), KEYS);
+    }
+
+    #[test]
+    fn test_pagination_within_one_inode() {
+        let file = File::open("/dev/null").unwrap();
+
+        assert_eq!(
+            search(TreeSearch::new(
+                &file,
+                Tree::Fs,
+                KeyRange::objectid(256),
+                0..=u64::MAX
+            )),
+            KEYS.iter()
+                .copied()
+                .filter(|&(objectid, _, _)| objectid == 256)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_range_is_compound() {
+        let file = File::open("/dev/null").unwrap();
+        let range = KeyRange::new(
+            Key::new(256, KeyType::ExtentData, 4096),
+            Key::new(257, KeyType::InodeRef, 256),
+        );
+
+        assert_eq!(
+            search(TreeSearch::new(&file, Tree::Fs, range, 0..=u64::MAX)),
+            &KEYS[2..7]
+        );
+    }
+
+    #[test]
+    fn test_search_of_type() {
+        let file = File::open("/dev/null").unwrap();
+
+        assert_eq!(
+            search(TreeSearch::of_type(&file, Tree::Fs, KeyType::InodeItem)),
+            KEYS.iter()
+                .copied()
+                .filter(|&(_, r#type, _)| r#type == KeyType::InodeItem as u32)
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_batches_resume_unread_items() {
+        let file = File::open("/dev/null").unwrap();
+        let mut search = TreeSearch::search_all(&file, Tree::Fs);
+        let mut keys = Vec::new();
+
+        search.ioctl = fake_tree_search;
+
+        // only take the first item of every batch, the second one has to come
+        // back at the start of the next batch
+        while let Some(batch) = search.next_batch() {
+            let (key, item) = batch.unwrap().next().unwrap().unwrap();
+
+            keys.push(match item {
+                ItemRef::Unknown { r#type, .. } => (key.objectid, u32::from(r#type), key.offset),
+                _ => (key.objectid, key.r#type as u32, key.offset),
+            });
+        }
+
+        assert_eq!(keys, KEYS);
+    }
+
+    #[test]
+    fn test_buffer_grows_for_large_items() {
+        let file = File::open("/dev/null").unwrap();
+        let mut search = TreeSearch::search_all(&file, Tree::Fs).with_buffer_size(4096);
+
+        search.ioctl = fake_large_item;
+
+        let items = search.map(Result::unwrap).collect::<Vec<_>>();
+
+        assert_eq!(items.len(), 1);
+        assert!(matches!(&items[0].1, Item::Unknown { data, .. } if data.len() == LARGE_ITEM));
+    }
+
+    unsafe fn fake_enotty(_: c_int, _: *mut btrfs_ioctl_search_args_v2) -> nix::Result<c_int> {
+        Err(nix::Error::ENOTTY)
+    }
+
+    unsafe fn fake_eperm(_: c_int, _: *mut btrfs_ioctl_search_args_v2) -> nix::Result<c_int> {
+        Err(nix::Error::EPERM)
+    }
+
+    #[test]
+    fn test_error_ends_search() {
+        let file = File::open("/dev/null").unwrap();
+        let mut search = TreeSearch::search_all(&file, Tree::Fs);
+
+        search.ioctl = fake_eperm;
+
+        assert!(matches!(
+            search.next(),
+            Some(Err(Error::Ioctl {
+                errno: nix::Error::EPERM,
+                ..
+            }))
+        ));
+        assert!(search.next().is_none());
+    }
+
+    // hands out the zero length items one at a time like v1 does when the
+    // item after them doesn't fit anymore
+    unsafe fn fake_v1_single(
+        args: *mut btrfs_ioctl_search_args_v2,
+        keys: &[(u64, u32, u64)],
+    ) -> nix::Result<c_int> {
+        let args = unsafe { &mut *args };
+        let min = (
+            args.key.min_objectid,
+            args.key.min_type,
+            args.key.min_offset,
+        );
+
+        let Some(&(objectid, r#type, offset)) = keys.iter().find(|&&key| key >= min) else {
+            args.key.nr_items = 0;
+
+            return Ok(0);
+        };
+
+        let header = btrfs_ioctl_search_header {
+            transid: 0,
+            objectid,
+            offset,
+            type_: r#type,
+            len: 0,
+        };
+
+        unsafe {
+            (args as *mut btrfs_ioctl_search_args_v2)
+                .cast::<u8>()
+                .add(mem::size_of::<btrfs_ioctl_search_args_v2>())
+                .cast::<btrfs_ioctl_search_header>()
+                .write_unaligned(header)
         };
 
-        self.bp +=
-            mem::size_of::<btrfs_ioctl_search_header>() + usize::try_from(header.len).unwrap();
-        self.args.key.min_objectid = header.objectid + 1;
-        self.args.key.min_offset = header.offset + 1;
-        self.args.key.min_type = header.type_ + 1;
-        self.args.key.nr_items -= 1;
+        args.key.nr_items = 1;
+
+        Ok(0)
+    }
+
+    // an xattr bigger than the v1 buffer comes back with its length zeroed
+    unsafe fn fake_v1_overflow(
+        _: c_int,
+        args: *mut btrfs_ioctl_search_args_v2,
+    ) -> nix::Result<c_int> {
+        unsafe {
+            fake_v1_single(
+                args,
+                &[
+                    (256, KeyType::XattrItem as u32, 0),
+                    (258, KeyType::OrphanItem as u32, 5),
+                ],
+            )
+        }
+    }
+
+    #[test]
+    fn test_v1_overflow() {
+        let file = File::open("/dev/null").unwrap();
+        let mut search = TreeSearch::search_all(&file, Tree::Fs);
+
+        search.ioctl = fake_enotty;
+        search.fallback = Some(fake_v1_overflow);
+
+        assert!(matches!(
+            search.next(),
+            Some(Err(Error::BufferTooSmall {
+                key: Some(Key {
+                    objectid: 256,
+                    r#type: KeyType::XattrItem,
+                    offset: 0,
+                }),
+                ..
+            }))
+        ));
+        assert!(matches!(search.next(), Some(Ok((_, Item::Orphan(_))))));
+        assert!(search.next().is_none());
+    }
+
+    // a backref really is empty, v1 hands it back alone when it's the last item
+    unsafe fn fake_v1_backref(
+        _: c_int,
+        args: *mut btrfs_ioctl_search_args_v2,
+    ) -> nix::Result<c_int> {
+        unsafe { fake_v1_single(args, &[(4096, KeyType::TreeBlockRef as u32, 5)]) }
+    }
+
+    #[test]
+    fn test_v1_empty_item() {
+        let file = File::open("/dev/null").unwrap();
+        let mut search = TreeSearch::search_all(&file, Tree::Extent);
+
+        search.ioctl = fake_enotty;
+        search.fallback = Some(fake_v1_backref);
+
+        let items = search.map(Result::unwrap).collect::<Vec<_>>();
+
+        assert_eq!(items.len(), 1);
+        assert!(matches!(items[0].1, Item::TreeBlockRef(_)));
+    }
+
+    #[test]
+    fn test_fallback_to_v1() {
+        let file = File::open("/dev/null").unwrap();
+        let mut search = TreeSearch::search_all(&file, Tree::Fs);
+
+        search.ioctl = fake_enotty;
+        search.fallback = Some(fake_tree_search);
 
-        Some(Ok((key, item)))
+        assert_eq!(
+            search
+                .map(|result| result.unwrap().0.objectid)
+                .collect::<Vec<_>>(),
+            KEYS.iter()
+                .map(|&(objectid, _, _)| objectid)
+                .collect::<Vec<_>>()
+        );
     }
 }
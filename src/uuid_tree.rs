@@ -0,0 +1,39 @@
+use crate::{
+    tree_search::{Item, Key, KeyRange, KeyType, Tree},
+    Error, TreeSearch, Uuid,
+};
+use std::fs::File;
+
+// subvolume uuids are unique, so there is at most one id
+pub fn find_subvol_by_uuid(file: &File, uuid: Uuid) -> Result<Option<u64>, Error> {
+    Ok(find_subvols(file, uuid, KeyType::UuidSubvol)?
+        .first()
+        .copied())
+}
+
+// every subvolume received from the same snapshot shares its received uuid
+pub fn find_subvol_by_received_uuid(file: &File, uuid: Uuid) -> Result<Vec<u64>, Error> {
+    find_subvols(file, uuid, KeyType::UuidReceivedSubvol)
+}
+
+fn find_subvols(file: &File, uuid: Uuid, key_type: KeyType) -> Result<Vec<u64>, Error> {
+    let (objectid, offset) = uuid.to_key();
+
+    let search = TreeSearch::new(
+        file,
+        Tree::Uuid,
+        KeyRange::key(Key::new(objectid, key_type, offset)),
+        0..=u64::MAX,
+    );
+
+    for result in search {
+        match result? {
+            (_, Item::UuidSubvol(item) | Item::UuidReceivedSubvol(item)) => {
+                return Ok(item.subvol_ids.iter().map(|id| id.get()).collect())
+            }
+            _ => continue,
+        }
+    }
+
+    Ok(Vec::new())
+}
@@ -0,0 +1,190 @@
+use crate::{
+    item::VerityDescItem,
+    tree_search::{Item, KeyRange, KeyType, Tree},
+    Error, TreeSearch,
+};
+use sha2::{Digest, Sha256, Sha512};
+use std::fs::File;
+
+const FS_VERITY_HASH_ALG_SHA256: u8 = 1;
+const FS_VERITY_HASH_ALG_SHA512: u8 = 2;
+
+// size of struct fsverity_descriptor, not counting the trailing signature
+const DESCRIPTOR_SIZE: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HashAlgorithm {
+    Sha256,
+    Sha512,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VerityDescriptor {
+    pub version: u8,
+    pub hash_algorithm: HashAlgorithm,
+    pub log_blocksize: u8,
+    pub data_size: u64,
+    pub root_hash: Vec<u8>,
+    pub salt: Vec<u8>,
+    pub sig_size: u32,
+    pub signature: Vec<u8>,
+    raw: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VerityDigest {
+    pub hash_algorithm: HashAlgorithm,
+    pub digest: Vec<u8>,
+}
+
+impl HashAlgorithm {
+    pub fn digest_size(self) -> usize {
+        match self {
+            Self::Sha256 => 32,
+            Self::Sha512 => 64,
+        }
+    }
+}
+
+impl VerityDescriptor {
+    pub(crate) fn from_bytes(data: &[u8]) -> Result<Self, Error> {
+        if data.len() < DESCRIPTOR_SIZE {
+            return Err(Error::Truncated {
+                key: None,
+                needed: DESCRIPTOR_SIZE,
+                available: data.len(),
+            });
+        }
+
+        let hash_algorithm = match data[1] {
+            FS_VERITY_HASH_ALG_SHA256 => HashAlgorithm::Sha256,
+            FS_VERITY_HASH_ALG_SHA512 => HashAlgorithm::Sha512,
+            _ => {
+                return Err(Error::Decode {
+                    key: None,
+                    what: "verity hash algorithm",
+                })
+            }
+        };
+
+        let salt_size = data[3] as usize;
+        let sig_size = u32::from_le_bytes(data[4..8].try_into().unwrap());
+
+        if salt_size > 32 || data.len() < DESCRIPTOR_SIZE + sig_size as usize {
+            return Err(Error::Decode {
+                key: None,
+                what: "verity descriptor",
+            });
+        }
+
+        Ok(Self {
+            version: data[0],
+            hash_algorithm,
+            log_blocksize: data[2],
+            data_size: u64::from_le_bytes(data[8..16].try_into().unwrap()),
+            root_hash: data[16..16 + hash_algorithm.digest_size()].to_vec(),
+            salt: data[80..80 + salt_size].to_vec(),
+            sig_size,
+            signature: data[DESCRIPTOR_SIZE..DESCRIPTOR_SIZE + sig_size as usize].to_vec(),
+            raw: data[..DESCRIPTOR_SIZE].to_vec(),
+        })
+    }
+
+    // the fs-verity file digest is the hash of the descriptor with sig_size
+    // zeroed and without the signature appended
+    pub fn digest(&self) -> VerityDigest {
+        let mut raw = self.raw.clone();
+
+        raw[4..8].fill(0);
+
+        VerityDigest {
+            hash_algorithm: self.hash_algorithm,
+            digest: match self.hash_algorithm {
+                HashAlgorithm::Sha256 => Sha256::digest(&raw).to_vec(),
+                HashAlgorithm::Sha512 => Sha512::digest(&raw).to_vec(),
+            },
+        }
+    }
+}
+
+pub fn verity_descriptor(
+    file: &File,
+    tree: Tree,
+    inode: u64,
+) -> Result<Option<VerityDescriptor>, Error> {
+    let search = TreeSearch::new(
+        file,
+        tree,
+        KeyRange::objectid_type(inode, KeyType::VerityDescItem),
+        0..=u64::MAX,
+    );
+
+    let mut descriptor: Option<Vec<u8>> = None;
+
+    for result in search {
+        match result? {
+            (_, Item::VerityDesc(VerityDescItem::Info { size, .. })) => {
+                descriptor = Some(vec![0; size.get() as usize]);
+            }
+            (key, Item::VerityDesc(VerityDescItem::Data { offset, data })) => {
+                let descriptor = descriptor.as_mut().ok_or(Error::Decode {
+                    key: Some(key),
+                    what: "verity descriptor without a size item",
+                })?;
+                let end = offset as usize + data.len();
+
+                descriptor
+                    .get_mut(offset as usize..end)
+                    .ok_or(Error::Decode {
+                        key: Some(key),
+                        what: "verity descriptor past its recorded size",
+                    })?
+                    .copy_from_slice(&data);
+            }
+            _ => continue,
+        }
+    }
+
+    descriptor
+        .map(|descriptor| VerityDescriptor::from_bytes(&descriptor))
+        .transpose()
+}
+
+pub fn verity_digest(file: &File, tree: Tree, inode: u64) -> Result<Option<VerityDigest>, Error> {
+    Ok(verity_descriptor(file, tree, inode)?.map(|descriptor| descriptor.digest()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn digest() {
+        // descriptor of an empty file with the default parameters, followed by
+        // a signature that mustn't be part of the digest
+        let mut data = vec![0; DESCRIPTOR_SIZE];
+
+        data[..4].copy_from_slice(&[1, FS_VERITY_HASH_ALG_SHA256, 12, 0]);
+        data[4..8].copy_from_slice(&4u32.to_le_bytes());
+        data.extend_from_slice(b"sig!");
+
+        let descriptor = VerityDescriptor::from_bytes(&data).unwrap();
+
+        assert_eq!(descriptor.sig_size, 4);
+        assert_eq!(descriptor.signature, b"sig!");
+
+        // what fsverity digest reports for an empty file
+        let expected = "3d248ca542a24fc62d1c43b916eae5016878e2533c88238480b26128a1f1af95";
+        let digest = descriptor.digest();
+
+        assert_eq!(digest.hash_algorithm, HashAlgorithm::Sha256);
+        assert_eq!(
+            digest
+                .digest
+                .iter()
+                .map(|byte| format!("{byte:02x}"))
+                .collect::<String>(),
+            expected
+        );
+    }
+}